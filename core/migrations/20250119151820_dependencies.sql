@@ -0,0 +1,10 @@
+CREATE TABLE dependencies (
+    profile_id INTEGER NOT NULL,
+    source_file TEXT NOT NULL,
+    dependency TEXT NOT NULL,
+    mtime INTEGER NOT NULL,
+    FOREIGN KEY (profile_id) REFERENCES profiles(id) ON DELETE CASCADE,
+    UNIQUE(profile_id, source_file, dependency)
+);
+
+CREATE INDEX idx_dependencies_profile_id ON dependencies (profile_id);
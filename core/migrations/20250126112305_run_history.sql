@@ -0,0 +1,24 @@
+CREATE TABLE runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    started_at INTEGER NOT NULL,
+    finished_at INTEGER NOT NULL,
+    arguments TEXT NOT NULL,
+    converted INTEGER NOT NULL,
+    skipped INTEGER NOT NULL,
+    failed INTEGER NOT NULL,
+    interrupted INTEGER NOT NULL
+);
+
+CREATE TABLE run_results (
+    run_id INTEGER NOT NULL,
+    profile_id INTEGER NOT NULL,
+    source_file TEXT NOT NULL,
+    status VARCHAR(12) NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    exit_code INTEGER,
+    message TEXT,
+    FOREIGN KEY (run_id) REFERENCES runs(id) ON DELETE CASCADE,
+    FOREIGN KEY (profile_id) REFERENCES profiles(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_run_results_run_id ON run_results (run_id);
@@ -0,0 +1,3 @@
+-- one extension or glob pattern per line
+ALTER TABLE profiles RENAME COLUMN source_file_extension TO source_file_extensions;
+ALTER TABLE profiles ADD COLUMN case_insensitive BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,52 @@
+-- further outputs of a profile, next to its own output extension and options
+CREATE TABLE targets (
+    profile_id INTEGER NOT NULL,
+    output_file_extension VARCHAR(10) NOT NULL,
+    options TEXT,
+    FOREIGN KEY (profile_id) REFERENCES profiles(id) ON DELETE CASCADE,
+    UNIQUE(profile_id, output_file_extension)
+);
+
+CREATE INDEX idx_targets_profile_id ON targets (profile_id);
+
+-- freshness is tracked per target, named after its output extension
+CREATE TABLE target_metadata (
+    profile_id INTEGER NOT NULL,
+    source_file TEXT NOT NULL,
+    target TEXT NOT NULL,
+    mtime INTEGER NOT NULL,
+    hash TEXT,
+    fingerprint TEXT,
+    FOREIGN KEY (profile_id) REFERENCES profiles(id) ON DELETE CASCADE,
+    UNIQUE(profile_id, source_file, target)
+);
+
+INSERT INTO target_metadata (profile_id, source_file, target, mtime, hash, fingerprint)
+SELECT metadata.profile_id, metadata.source_file, profiles.output_file_extension,
+    metadata.mtime, metadata.hash, metadata.fingerprint
+FROM metadata JOIN profiles ON profiles.id = metadata.profile_id;
+
+DROP TABLE metadata;
+ALTER TABLE target_metadata RENAME TO metadata;
+CREATE INDEX idx_metadata_profile_id ON metadata (profile_id);
+
+CREATE TABLE target_dependencies (
+    profile_id INTEGER NOT NULL,
+    source_file TEXT NOT NULL,
+    target TEXT NOT NULL,
+    dependency TEXT NOT NULL,
+    mtime INTEGER NOT NULL,
+    FOREIGN KEY (profile_id) REFERENCES profiles(id) ON DELETE CASCADE,
+    UNIQUE(profile_id, source_file, target, dependency)
+);
+
+INSERT INTO target_dependencies (profile_id, source_file, target, dependency, mtime)
+SELECT dependencies.profile_id, dependencies.source_file, profiles.output_file_extension,
+    dependencies.dependency, dependencies.mtime
+FROM dependencies JOIN profiles ON profiles.id = dependencies.profile_id;
+
+DROP TABLE dependencies;
+ALTER TABLE target_dependencies RENAME TO dependencies;
+CREATE INDEX idx_dependencies_profile_id ON dependencies (profile_id);
+
+ALTER TABLE run_results ADD COLUMN output_file TEXT;
@@ -0,0 +1,4 @@
+-- NULL mirrors the source tree under the output root
+ALTER TABLE profiles ADD COLUMN output_template TEXT;
+-- where a target was written, as templates may not be rendered again once the source is gone
+ALTER TABLE metadata ADD COLUMN output_file TEXT;
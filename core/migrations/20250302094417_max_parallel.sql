@@ -0,0 +1,2 @@
+-- NULL lets a profile convert as many files at once as the run has jobs
+ALTER TABLE profiles ADD COLUMN max_parallel INTEGER;
@@ -0,0 +1,4 @@
+-- seconds a file may take to convert; NULL waits indefinitely
+ALTER TABLE profiles ADD COLUMN timeout INTEGER;
+-- files whose conversion ran out of time
+ALTER TABLE runs ADD COLUMN timed_out INTEGER NOT NULL DEFAULT 0;
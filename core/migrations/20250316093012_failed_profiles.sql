@@ -0,0 +1,2 @@
+-- profiles that could not run at all (missing engine, invalid pattern or template)
+ALTER TABLE runs ADD COLUMN failed_profiles INTEGER NOT NULL DEFAULT 0;
@@ -1,13 +1,23 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use directories::ProjectDirs;
+use filetime::FileTime;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
     fs::{self, ReadDir},
-    path::PathBuf,
+    future::Future,
+    path::{Path, PathBuf},
     sync::{Arc, Condvar, LazyLock, Mutex},
+    time::Duration,
 };
 use tokio::runtime::Handle;
 
+use crate::error::TernError;
+
+/// Slot the database fills with the outcome of a request, see [`respond`] and [`wait_response`]
+pub type Response<T> = Arc<(Mutex<Option<Result<T, TernError>>>, Condvar)>;
+
 pub enum ModelEvent {
     ReadEvent,
     WriteEvent,
@@ -20,39 +30,84 @@ pub enum AgentEvent {
 }
 
 pub enum ReadEvent {
-    GetColumn(Arc<(Mutex<Vec<String>>, Condvar)>, String),
-    GetProfiles(Arc<(Mutex<Vec<Profile>>, Condvar)>),
+    GetColumn(Response<Vec<String>>, String),
+    GetProfiles(Response<Vec<Profile>>),
+    GetRuns(Response<Vec<RunRecord>>, u32),
+    GetFailedFiles(Response<Vec<(u8, String)>>),
 }
 
 pub enum WriteEvent {
-    StoreProfile(Option<Arc<Profile>>),
-    UpdateMetadata((PathBuf, u8)),
+    StoreProfile(Option<Arc<Profile>>, Response<()>),
+    UpdateProfile(Option<Arc<Profile>>, Response<()>),
+    DeleteProfile(u8, Response<()>),
+    UpdateMetadata((PathBuf, u8, String, PathBuf, String, Vec<PathBuf>)),
+    RemoveMetadata((PathBuf, u8)),
+    RefreshMtime((PathBuf, u8, String)),
+    StoreRun(Run),
 }
 
 pub trait ModelMessageBroker {
-    async fn send_write_event(tx: tokio::sync::oneshot::Sender<ModelEvent>);
-    async fn send_read_event(tx: tokio::sync::oneshot::Sender<ModelEvent>);
+    fn send_write_event(
+        tx: tokio::sync::oneshot::Sender<ModelEvent>,
+    ) -> impl Future<Output = ()> + Send;
+    fn send_read_event(
+        tx: tokio::sync::oneshot::Sender<ModelEvent>,
+    ) -> impl Future<Output = ()> + Send;
 }
 
 pub trait AgentMessageBroker {
-    async fn send_get_column_event(
+    fn send_get_column_event(
         tx: tokio::sync::mpsc::Sender<AgentEvent>,
-        column: Arc<(Mutex<Vec<String>>, Condvar)>,
+        column: Response<Vec<String>>,
         kind: String,
-    );
-    async fn send_get_profiles_event(
+    ) -> impl Future<Output = ()> + Send;
+    fn send_get_profiles_event(
         tx: tokio::sync::mpsc::Sender<AgentEvent>,
-        profile: Arc<(Mutex<Vec<Profile>>, Condvar)>,
-    );
-    async fn send_store_profile_event(
+        profile: Response<Vec<Profile>>,
+    ) -> impl Future<Output = ()> + Send;
+    fn send_store_profile_event(
         tx: tokio::sync::mpsc::Sender<AgentEvent>,
         profile: Arc<Profile>,
-    );
-    async fn send_update_metadata_event(
+        response: Response<()>,
+    ) -> impl Future<Output = ()> + Send;
+    fn send_update_profile_event(
+        tx: tokio::sync::mpsc::Sender<AgentEvent>,
+        profile: Arc<Profile>,
+        response: Response<()>,
+    ) -> impl Future<Output = ()> + Send;
+    fn send_delete_profile_event(
+        tx: tokio::sync::mpsc::Sender<AgentEvent>,
+        profile_id: u8,
+        response: Response<()>,
+    ) -> impl Future<Output = ()> + Send;
+    fn send_update_metadata_event(
+        tx: tokio::sync::mpsc::Sender<AgentEvent>,
+        metadata: (PathBuf, u8, String, PathBuf, String, Vec<PathBuf>),
+    ) -> impl Future<Output = ()> + Send;
+    fn send_remove_metadata_event(
         tx: tokio::sync::mpsc::Sender<AgentEvent>,
         metadata: (PathBuf, u8),
-    );
-    async fn send_quit_event(tx: tokio::sync::mpsc::Sender<AgentEvent>);
+    ) -> impl Future<Output = ()> + Send;
+    fn send_refresh_mtime_event(
+        tx: tokio::sync::mpsc::Sender<AgentEvent>,
+        metadata: (PathBuf, u8, String),
+    ) -> impl Future<Output = ()> + Send;
+    fn send_get_runs_event(
+        tx: tokio::sync::mpsc::Sender<AgentEvent>,
+        runs: Response<Vec<RunRecord>>,
+        limit: u32,
+    ) -> impl Future<Output = ()> + Send;
+    fn send_get_failed_files_event(
+        tx: tokio::sync::mpsc::Sender<AgentEvent>,
+        failed_files: Response<Vec<(u8, String)>>,
+    ) -> impl Future<Output = ()> + Send;
+    fn send_store_run_event(
+        tx: tokio::sync::mpsc::Sender<AgentEvent>,
+        run: Run,
+    ) -> impl Future<Output = ()> + Send;
+    fn send_quit_event(
+        tx: tokio::sync::mpsc::Sender<AgentEvent>,
+    ) -> impl Future<Output = ()> + Send;
 }
 
 pub struct Controller;
@@ -74,7 +129,7 @@ impl ModelMessageBroker for Controller {
 impl AgentMessageBroker for Controller {
     async fn send_get_column_event(
         tx: tokio::sync::mpsc::Sender<AgentEvent>,
-        column: Arc<(Mutex<Vec<String>>, Condvar)>,
+        column: Response<Vec<String>>,
         kind: String,
     ) {
         if (tx
@@ -91,7 +146,7 @@ impl AgentMessageBroker for Controller {
 
     async fn send_get_profiles_event(
         tx: tokio::sync::mpsc::Sender<AgentEvent>,
-        profile: Arc<(Mutex<Vec<Profile>>, Condvar)>,
+        profile: Response<Vec<Profile>>,
     ) {
         if (tx
             .send(AgentEvent::ReadEvent(ReadEvent::GetProfiles(Arc::clone(
@@ -107,11 +162,13 @@ impl AgentMessageBroker for Controller {
     async fn send_store_profile_event(
         tx: tokio::sync::mpsc::Sender<AgentEvent>,
         profile: Arc<Profile>,
+        response: Response<()>,
     ) {
         if (tx
-            .send(AgentEvent::WriteEvent(WriteEvent::StoreProfile(Some(
-                profile,
-            ))))
+            .send(AgentEvent::WriteEvent(WriteEvent::StoreProfile(
+                Some(profile),
+                Arc::clone(&response),
+            )))
             .await)
             .is_err()
         {
@@ -119,9 +176,43 @@ impl AgentMessageBroker for Controller {
         }
     }
 
+    async fn send_update_profile_event(
+        tx: tokio::sync::mpsc::Sender<AgentEvent>,
+        profile: Arc<Profile>,
+        response: Response<()>,
+    ) {
+        if (tx
+            .send(AgentEvent::WriteEvent(WriteEvent::UpdateProfile(
+                Some(profile),
+                Arc::clone(&response),
+            )))
+            .await)
+            .is_err()
+        {
+            panic!("Receiver dropped before message [AgentEvent::WriteEvent(WriteEvent::UpdateProfile(arc))] could be sent");
+        }
+    }
+
+    async fn send_delete_profile_event(
+        tx: tokio::sync::mpsc::Sender<AgentEvent>,
+        profile_id: u8,
+        response: Response<()>,
+    ) {
+        if (tx
+            .send(AgentEvent::WriteEvent(WriteEvent::DeleteProfile(
+                profile_id,
+                Arc::clone(&response),
+            )))
+            .await)
+            .is_err()
+        {
+            panic!("Receiver dropped before message [AgentEvent::WriteEvent(WriteEvent::DeleteProfile(id))] could be sent");
+        }
+    }
+
     async fn send_update_metadata_event(
         tx: tokio::sync::mpsc::Sender<AgentEvent>,
-        metadata: (PathBuf, u8),
+        metadata: (PathBuf, u8, String, PathBuf, String, Vec<PathBuf>),
     ) {
         if (tx
             .send(AgentEvent::WriteEvent(WriteEvent::UpdateMetadata(metadata)))
@@ -132,6 +223,74 @@ impl AgentMessageBroker for Controller {
         }
     }
 
+    async fn send_remove_metadata_event(
+        tx: tokio::sync::mpsc::Sender<AgentEvent>,
+        metadata: (PathBuf, u8),
+    ) {
+        if (tx
+            .send(AgentEvent::WriteEvent(WriteEvent::RemoveMetadata(metadata)))
+            .await)
+            .is_err()
+        {
+            panic!("Receiver dropped before message [AgentEvent::WriteEvent(WriteEvent::RemoveMetadata(metadata))] could be sent");
+        }
+    }
+
+    async fn send_refresh_mtime_event(
+        tx: tokio::sync::mpsc::Sender<AgentEvent>,
+        metadata: (PathBuf, u8, String),
+    ) {
+        if (tx
+            .send(AgentEvent::WriteEvent(WriteEvent::RefreshMtime(metadata)))
+            .await)
+            .is_err()
+        {
+            panic!("Receiver dropped before message [AgentEvent::WriteEvent(WriteEvent::RefreshMtime(metadata))] could be sent");
+        }
+    }
+
+    async fn send_get_runs_event(
+        tx: tokio::sync::mpsc::Sender<AgentEvent>,
+        runs: Response<Vec<RunRecord>>,
+        limit: u32,
+    ) {
+        if (tx
+            .send(AgentEvent::ReadEvent(ReadEvent::GetRuns(
+                Arc::clone(&runs),
+                limit,
+            )))
+            .await)
+            .is_err()
+        {
+            panic!("Receiver dropped before message [AgentEvent::ReadEvent(ReadEvent::GetRuns(arc))] could be sent");
+        }
+    }
+
+    async fn send_get_failed_files_event(
+        tx: tokio::sync::mpsc::Sender<AgentEvent>,
+        failed_files: Response<Vec<(u8, String)>>,
+    ) {
+        if (tx
+            .send(AgentEvent::ReadEvent(ReadEvent::GetFailedFiles(
+                Arc::clone(&failed_files),
+            )))
+            .await)
+            .is_err()
+        {
+            panic!("Receiver dropped before message [AgentEvent::ReadEvent(ReadEvent::GetFailedFiles(arc))] could be sent");
+        }
+    }
+
+    async fn send_store_run_event(tx: tokio::sync::mpsc::Sender<AgentEvent>, run: Run) {
+        if (tx
+            .send(AgentEvent::WriteEvent(WriteEvent::StoreRun(run)))
+            .await)
+            .is_err()
+        {
+            panic!("Receiver dropped before message [AgentEvent::WriteEvent(WriteEvent::StoreRun(run))] could be sent");
+        }
+    }
+
     async fn send_quit_event(tx: tokio::sync::mpsc::Sender<AgentEvent>) {
         if (tx.send(AgentEvent::Quit).await).is_err() {
             println!("Receiver dropped before message [AgentEvent::Quit] could be sent");
@@ -144,12 +303,134 @@ pub struct Profile {
     pub id: u8,
     pub engine: String,
     pub source_root: String,
-    pub source_file_extension: String,
+    // extensions (`md`) or glob patterns matched against file names (`*.tar.gz`)
+    pub source_file_extensions: Vec<String>,
+    pub case_insensitive: bool,
     pub output_root: String,
     pub output_file_extension: String,
+    // output path layout, see `render_output_template`; None mirrors the source tree
+    pub output_template: Option<String>,
     pub options: Option<Vec<String>>,
+    // outputs converted next to the one above, each with its own extension and options
+    pub targets: Option<Vec<Target>>,
     pub ignore_patterns: Option<Vec<String>>,
-    pub metadata: Option<BTreeMap<String, i64>>,
+    pub tags: Option<Vec<String>>,
+    // files converted at once at most, for engines that break when run concurrently
+    pub max_parallel: Option<u32>,
+    // seconds a file may take to convert before its processes are killed
+    pub timeout: Option<u32>,
+    // tracked source files, then their targets by output extension
+    pub metadata: Option<BTreeMap<String, BTreeMap<String, FileMetadata>>>,
+}
+
+/// An output converted from every source file of a profile
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub output_file_extension: String,
+    pub options: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub struct FileMetadata {
+    pub mtime: i64,
+    pub hash: Option<String>,
+    pub fingerprint: Option<String>,
+    pub output_file: Option<String>,
+    pub dependencies: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConversionStatus {
+    Converted,
+    Skipped,
+    Failed,
+    Interrupted,
+    TimedOut,
+}
+
+impl ConversionStatus {
+    /// Whether the file is retried by `--retry-failed` and listed in the run summary
+    pub fn is_failure(&self) -> bool {
+        matches!(self, ConversionStatus::Failed | ConversionStatus::TimedOut)
+    }
+}
+
+impl fmt::Display for ConversionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self {
+            ConversionStatus::Converted => "converted",
+            ConversionStatus::Skipped => "skipped",
+            ConversionStatus::Failed => "failed",
+            ConversionStatus::Interrupted => "interrupted",
+            ConversionStatus::TimedOut => "timed_out",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+#[derive(Debug)]
+pub struct ConversionResult {
+    pub profile_id: u8,
+    pub source_file: PathBuf,
+    pub output_file: PathBuf,
+    pub status: ConversionStatus,
+    pub duration: Duration,
+    pub exit_code: Option<i64>,
+    pub output: Option<String>,
+}
+
+impl ConversionResult {
+    pub fn new(
+        profile_id: u8,
+        source_file: PathBuf,
+        output_file: PathBuf,
+        status: ConversionStatus,
+    ) -> Self {
+        Self {
+            profile_id,
+            source_file,
+            output_file,
+            status,
+            duration: Duration::ZERO,
+            exit_code: None,
+            output: None,
+        }
+    }
+
+    /// Describes the outcome using the captured output, falling back to the exit code
+    pub fn message(&self) -> String {
+        match (&self.output, self.exit_code) {
+            (Some(output), _) if !output.trim().is_empty() => output.trim().to_string(),
+            (_, Some(exit_code)) => format!("exited with code {}", exit_code),
+            _ => self.status.to_string(),
+        }
+    }
+}
+
+/// A finished run, as stored in the database
+#[derive(Debug)]
+pub struct Run {
+    pub started_at: i64,
+    pub finished_at: i64,
+    pub arguments: String,
+    pub results: Vec<ConversionResult>,
+    /// Profiles that could not run at all (missing engine, invalid pattern or template)
+    pub failed_profiles: u32,
+}
+
+/// A stored run, as listed by `tern history`
+#[derive(Debug)]
+pub struct RunRecord {
+    pub id: u32,
+    pub started_at: String,
+    pub finished_at: String,
+    pub arguments: String,
+    pub converted: u32,
+    pub skipped: u32,
+    pub failed: u32,
+    pub interrupted: u32,
+    pub timed_out: u32,
+    pub failed_profiles: u32,
 }
 
 #[derive(Parser)]
@@ -159,29 +440,209 @@ pub struct ArgParser {
     pub tui: bool,
     #[arg(short, long, action)]
     pub profile_manager: bool,
-    #[arg(short, long, default_value_t = true)]
+    #[arg(short, long, default_value_t = true, global = true)]
     pub ignore_hidden_files: bool,
-    #[arg(short, long, action)]
+    #[arg(short, long, action, global = true)]
     pub force: bool,
-    #[arg(short = 's', long, action)]
+    #[arg(short = 's', long, action, global = true)]
     pub follow_symlinks: bool,
-    #[arg(long, action)]
+    #[arg(long, action, global = true)]
     pub concurrent_profiles: bool,
+    /// Files converted at once across every profile (the number of cores by default)
+    #[arg(short, long, global = true)]
+    pub jobs: Option<usize>,
+    #[arg(long, action, global = true)]
+    pub content_hash: bool,
+    #[arg(long, action, global = true)]
+    pub prune: bool,
+    #[arg(short = 'n', long, action, global = true)]
+    pub dry_run: bool,
+    #[arg(long, action, global = true)]
+    pub retry_failed: bool,
+    #[arg(short, long, action, global = true, conflicts_with_all = ["dry_run", "retry_failed"])]
+    pub watch: bool,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, global = true)]
+    pub output_format: OutputFormat,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    /// One JSON event per line (NDJSON)
+    Json,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum Command {
+    /// Runs conversions, optionally for some profiles only
+    Run(ProfileSelection),
+    /// Removes outputs and metadata of deleted or renamed source files (`--dry-run` lists them)
+    Prune,
+    /// Lists recent runs
+    History {
+        /// Number of runs to list
+        #[arg(short, long, default_value_t = 10)]
+        limit: u32,
+    },
+    /// Manages stored profiles without the profile manager
+    Profile {
+        #[command(subcommand)]
+        action: ProfileCommand,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum ProfileCommand {
+    /// Stores a new profile
+    Add {
+        /// Converter script found under the converters directory
+        #[arg(long)]
+        engine: String,
+        #[arg(long)]
+        source_root: String,
+        /// Extension or glob pattern of the source files (repeatable)
+        #[arg(long = "source-file-extension", required = true)]
+        source_file_extensions: Vec<String>,
+        /// Matches source file extensions regardless of case
+        #[arg(long, action)]
+        case_insensitive: bool,
+        #[arg(long)]
+        output_root: String,
+        #[arg(long)]
+        output_file_extension: String,
+        /// Output path layout: '{output_root}/{year}/{stem}-{hash8}.{ext}' (mirrors the source tree by default)
+        #[arg(long)]
+        output_template: Option<String>,
+        /// Writes every output directly under the output root
+        #[arg(long, action, conflicts_with = "output_template")]
+        flatten: bool,
+        /// Option handed to the converter (repeatable)
+        #[arg(long = "option", allow_hyphen_values = true)]
+        options: Vec<String>,
+        /// Further output, as its extension followed by its options: 'pdf --toc' (repeatable)
+        #[arg(long = "target", allow_hyphen_values = true)]
+        targets: Vec<String>,
+        /// Git ignore pattern selecting files (repeatable)
+        #[arg(long = "ignore-pattern")]
+        ignore_patterns: Vec<String>,
+        /// Tag used to select the profile with `tern run --tag` (repeatable)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Files converted at once at most (bounded by `--jobs` as well)
+        #[arg(long)]
+        max_parallel: Option<u32>,
+        /// Seconds a file may take to convert before the processes it started are killed
+        #[arg(long)]
+        timeout: Option<u32>,
+    },
+    /// Lists stored profiles
+    List,
+    /// Shows every field of a profile
+    Show { id: u8 },
+    /// Changes the given fields of a profile
+    Edit {
+        id: u8,
+        /// Converter script found under the converters directory
+        #[arg(long)]
+        engine: Option<String>,
+        #[arg(long)]
+        source_root: Option<String>,
+        /// Replaces the extensions or glob patterns of the source files (repeatable)
+        #[arg(long = "source-file-extension")]
+        source_file_extensions: Option<Vec<String>>,
+        /// Matches source file extensions regardless of case
+        #[arg(long)]
+        case_insensitive: Option<bool>,
+        #[arg(long)]
+        output_root: Option<String>,
+        #[arg(long)]
+        output_file_extension: Option<String>,
+        /// Replaces the output path layout (`--output-template ''` mirrors the source tree again)
+        #[arg(long)]
+        output_template: Option<String>,
+        /// Writes every output directly under the output root
+        #[arg(long, action, conflicts_with = "output_template")]
+        flatten: bool,
+        /// Replaces the options handed to the converter (repeatable, `--option ''` clears them)
+        #[arg(long = "option", allow_hyphen_values = true)]
+        options: Option<Vec<String>>,
+        /// Replaces the further outputs (repeatable, `--target ''` clears them)
+        #[arg(long = "target", allow_hyphen_values = true)]
+        targets: Option<Vec<String>>,
+        /// Replaces the git ignore patterns (repeatable, `--ignore-pattern ''` clears them)
+        #[arg(long = "ignore-pattern")]
+        ignore_patterns: Option<Vec<String>>,
+        /// Replaces the tags (repeatable, `--tag ''` clears them)
+        #[arg(long = "tag")]
+        tags: Option<Vec<String>>,
+        /// Files converted at once at most (`--max-parallel 0` lifts the limit)
+        #[arg(long)]
+        max_parallel: Option<u32>,
+        /// Seconds a file may take to convert (`--timeout 0` waits indefinitely)
+        #[arg(long)]
+        timeout: Option<u32>,
+    },
+    /// Removes a profile along with its tracked files
+    Remove { id: u8 },
+}
+
+/// Profiles a run is restricted to; every given kind of criterion must match
+#[derive(Args, Clone, Default)]
+pub struct ProfileSelection {
+    /// Runs the profile with this id (repeatable)
+    #[arg(long = "profile")]
+    pub ids: Vec<u8>,
+    /// Runs the profiles using this engine (repeatable)
+    #[arg(long = "engine")]
+    pub engines: Vec<String>,
+    /// Runs the profiles carrying this tag (repeatable)
+    #[arg(long = "tag")]
+    pub tags: Vec<String>,
+}
+
+impl ProfileSelection {
+    pub fn matches(&self, profile: &Profile) -> bool {
+        (self.ids.is_empty() || self.ids.contains(&profile.id))
+            && (self.engines.is_empty() || self.engines.contains(&profile.engine))
+            && (self.tags.is_empty()
+                || profile
+                    .tags
+                    .as_deref()
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|tag| self.tags.contains(tag)))
+    }
 }
 
 pub struct DatabaseArgs {
     pub profile_manager: bool,
+    // subcommands never open the profile manager
+    pub headless: bool,
+    pub content_hash: bool,
 }
 
 pub struct InterfaceArgs {
     pub tui: bool,
+    // conversions started from an interface honor the command line flags
+    pub converter_args: ConverterArgs,
 }
 
+#[derive(Clone)]
 pub struct ConverterArgs {
     pub hidden: bool,
     pub force: bool,
     pub concurrent_profiles: bool,
+    pub jobs: Option<usize>,
     pub follow_symlinks: bool,
+    pub content_hash: bool,
+    pub prune: bool,
+    pub dry_run: bool,
+    pub retry_failed: bool,
+    pub watch: bool,
+    pub output_format: OutputFormat,
+    pub selection: ProfileSelection,
 }
 
 static ASYNC_RUNTIME_HANDLE: LazyLock<Handle> = LazyLock::new(|| Handle::current());
@@ -201,6 +662,22 @@ pub fn get_converters_dir() -> PathBuf {
     CONVERTERS_DIR.clone()
 }
 
+// Hands the outcome of a request to the agent waiting for it
+pub fn respond<T>(response: Response<T>, result: Result<T, TernError>) {
+    *response.0.lock().unwrap() = Some(result);
+    response.1.notify_one();
+}
+
+// Blocks until the database has answered a request; agents run on blocking threads for this,
+// a runtime worker waiting here could starve the database of the worker it needs to answer
+pub fn wait_response<T>(response: &Response<T>) -> Result<T, TernError> {
+    let (lock, cvar) = &**response;
+    cvar.wait_while(lock.lock().unwrap(), |result| result.is_none())
+        .unwrap()
+        .take()
+        .unwrap()
+}
+
 // Creates data dir if it does not exist
 pub fn create_data_dir() {
     fs::create_dir_all(&*CONVERTERS_DIR).unwrap();
@@ -210,3 +687,490 @@ pub fn create_data_dir() {
 pub fn read_data_dir() -> ReadDir {
     fs::read_dir(&*CONVERTERS_DIR).unwrap()
 }
+
+// Matches file names against the source file extensions (or glob patterns) of a profile
+pub fn source_file_matcher(profile: &Profile) -> Result<GlobSet, TernError> {
+    let mut builder = GlobSetBuilder::new();
+    for extension in &profile.source_file_extensions {
+        let pattern = if extension.contains(['*', '?', '[', '{']) {
+            extension.clone()
+        } else {
+            format!("*.{}", extension.trim_start_matches('.'))
+        };
+        builder.add(
+            GlobBuilder::new(&pattern)
+                .case_insensitive(profile.case_insensitive)
+                .literal_separator(true)
+                .build()?,
+        );
+    }
+    Ok(builder.build()?)
+}
+
+// Splits source file extensions typed in a single field ("md, markdown")
+pub fn parse_source_file_extensions(value: &str) -> Vec<String> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|extension| !extension.is_empty())
+        .map(Into::into)
+        .collect()
+}
+
+// Reads a limit (max parallel conversions, timeout) typed in a single field; empty or 0 sets none
+pub fn parse_limit(limit: &'static str, value: &str) -> Result<Option<u32>, TernError> {
+    match value.trim() {
+        "" => Ok(None),
+        value => value
+            .parse::<u32>()
+            .map(|number| (number > 0).then_some(number))
+            .map_err(|_| TernError::InvalidLimit(limit, value.to_string())),
+    }
+}
+
+// Returns the BLAKE3 digest of a file's contents
+pub fn hash_file(path: &Path) -> Option<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(fs::File::open(path).ok()?).ok()?;
+    Some(hasher.finalize().to_hex().to_string())
+}
+
+// Returns the outputs of a profile: its own, then its further targets
+pub fn profile_targets(profile: &Profile) -> Vec<Target> {
+    let mut targets = vec![Target {
+        output_file_extension: profile.output_file_extension.clone(),
+        options: profile.options.clone(),
+    }];
+    targets.extend(profile.targets.iter().flatten().cloned());
+    targets
+}
+
+// Fails when an output extension is targeted twice, their outputs would overwrite each other
+pub fn check_targets(profile: &Profile) -> Result<(), TernError> {
+    let mut extensions = BTreeSet::new();
+    for target in profile_targets(profile) {
+        if !extensions.insert(target.output_file_extension.clone()) {
+            Err(TernError::DuplicateTarget(target.output_file_extension))?;
+        }
+    }
+    Ok(())
+}
+
+// Parses a target typed as its output extension followed by its options ("pdf --toc")
+pub fn parse_target(value: &str) -> Result<Target, TernError> {
+    let invalid = || TernError::InvalidTarget(value.to_string());
+    let mut words = shlex::split(value).ok_or_else(invalid)?.into_iter();
+    let output_file_extension = words.next().ok_or_else(invalid)?;
+    let options: Vec<String> = words.collect();
+    Ok(Target {
+        output_file_extension,
+        options: (!options.is_empty()).then_some(options),
+    })
+}
+
+// Formats a target the way `parse_target` reads it
+pub fn format_target(target: &Target) -> String {
+    std::iter::once(&target.output_file_extension)
+        .chain(target.options.iter().flatten())
+        .map(|word| shlex::try_quote(word).map_or(word.clone(), Into::into))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub const FLATTEN_TEMPLATE: &str = "{output_root}/{stem}.{ext}";
+
+pub const TEMPLATE_PLACEHOLDERS: [&str; 11] = [
+    "output_root",
+    "path",
+    "parent_name",
+    "name",
+    "stem",
+    "source_ext",
+    "ext",
+    "year",
+    "month",
+    "day",
+    "hash8",
+];
+
+// Fails on unknown placeholders and unbalanced braces, and on templates whose outputs could
+// land outside of the output root, where pruning would remove them
+pub fn check_output_template(template: &str) -> Result<(), TernError> {
+    fill_template(template, |_| String::new())?;
+    relative_template(template)?;
+    Ok(())
+}
+
+// Returns the part of a template laid out below the output root; the output root itself may
+// climb up (`../site`), the part below it may not
+fn relative_template(template: &str) -> Result<&str, TernError> {
+    template
+        .strip_prefix("{output_root}/")
+        .filter(|relative| !relative.split('/').any(|segment| segment == ".."))
+        .ok_or_else(|| TernError::TemplateOutsideOutputRoot(template.to_string()))
+}
+
+// Renders the output path of a source file converted to a target:
+// - output_root: the profile's output root
+// - path: directory of the source file relative to the source root
+// - parent_name: name of the directory holding the source file
+// - name, stem, source_ext: file name of the source file, without and only its extension
+// - ext: extension of the target
+// - year, month, day: modification date of the source file (UTC)
+// - hash8: first 8 hex digits of the BLAKE3 digest of the path relative to the source root
+pub fn render_output_template(
+    template: &str,
+    profile: &Profile,
+    target: &Target,
+    source_file: &Path,
+) -> Result<PathBuf, TernError> {
+    let relative = source_file
+        .strip_prefix(&profile.source_root)
+        .unwrap_or(source_file);
+    let lossy = |path: Option<&std::ffi::OsStr>| {
+        path.map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let date = || {
+        fs::metadata(source_file)
+            .map(|metadata| {
+                civil_date(FileTime::from_last_modification_time(&metadata).unix_seconds())
+            })
+            .ok()
+    };
+    let below_output_root = relative_template(template)?;
+    let rendered = fill_template(below_output_root, |placeholder| match placeholder {
+        "output_root" => profile.output_root.clone(),
+        "path" => relative
+            .parent()
+            .map(|parent| parent.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        "parent_name" => lossy(source_file.parent().and_then(Path::file_name)),
+        "name" => lossy(source_file.file_name()),
+        "stem" => lossy(source_file.file_stem()),
+        "source_ext" => lossy(source_file.extension()),
+        "ext" => target.output_file_extension.clone(),
+        "year" => date().map_or_else(String::new, |(year, _, _)| year.to_string()),
+        "month" => date().map_or_else(String::new, |(_, month, _)| format!("{:02}", month)),
+        "day" => date().map_or_else(String::new, |(_, _, day)| format!("{:02}", day)),
+        "hash8" => blake3::hash(relative.to_string_lossy().as_bytes()).to_hex()[..8].to_string(),
+        _ => unreachable!(),
+    })?;
+    // empty placeholders ({path} at the source root) leave no empty directory behind, and
+    // placeholders must not lead out of the output root either
+    let mut output_file = PathBuf::from(&profile.output_root);
+    for segment in rendered.split('/').filter(|segment| !segment.is_empty()) {
+        if segment == ".." {
+            Err(TernError::TemplateOutsideOutputRoot(template.to_string()))?;
+        }
+        output_file.push(segment);
+    }
+    Ok(output_file)
+}
+
+fn fill_template(template: &str, value: impl Fn(&str) -> String) -> Result<String, TernError> {
+    let invalid = || TernError::InvalidTemplate(template.to_string());
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        if rest[start..].starts_with('}') {
+            Err(invalid())?;
+        }
+        let end = start + rest[start..].find('}').ok_or_else(invalid)?;
+        let placeholder = &rest[start + 1..end];
+        if !TEMPLATE_PLACEHOLDERS.contains(&placeholder) {
+            Err(invalid())?;
+        }
+        rendered.push_str(&rest[..start]);
+        rendered.push_str(&value(placeholder));
+        rest = &rest[end + 1..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+// Converts a unix time into a (year, month, day) date of the proleptic Gregorian calendar
+fn civil_date(unix_seconds: i64) -> (i64, u32, u32) {
+    let days = unix_seconds.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    (year_of_era + era * 400 + (month <= 2) as i64, month, day)
+}
+
+// Returns the BLAKE3 digest of the converter script, options and output extension of a target
+pub fn target_fingerprint(profile: &Profile, target: &Target) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&fs::read(CONVERTERS_DIR.join(&profile.engine)).unwrap_or_default());
+    hasher.update(&[0]);
+    hasher.update(
+        target
+            .options
+            .as_deref()
+            .unwrap_or_default()
+            .join("\n")
+            .as_bytes(),
+    );
+    hasher.update(&[0]);
+    hasher.update(target.output_file_extension.as_bytes());
+    hasher.finalize().to_hex().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(id: u8, engine: &str, tags: &[&str]) -> Profile {
+        Profile {
+            id,
+            engine: engine.to_string(),
+            source_root: "/notes".to_string(),
+            source_file_extensions: vec!["md".to_string()],
+            case_insensitive: false,
+            output_root: "/site".to_string(),
+            output_file_extension: "html".to_string(),
+            output_template: None,
+            options: None,
+            targets: None,
+            ignore_patterns: None,
+            tags: (!tags.is_empty()).then(|| tags.iter().map(|tag| tag.to_string()).collect()),
+            max_parallel: None,
+            timeout: None,
+            metadata: None,
+        }
+    }
+
+    fn selection(ids: &[u8], engines: &[&str], tags: &[&str]) -> ProfileSelection {
+        ProfileSelection {
+            ids: ids.to_vec(),
+            engines: engines.iter().map(|engine| engine.to_string()).collect(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn empty_selection_matches_every_profile() {
+        assert!(selection(&[], &[], &[]).matches(&profile(1, "pandoc", &[])));
+    }
+
+    #[test]
+    fn selection_matches_any_value_of_a_criterion() {
+        let selection = selection(&[], &["pandoc", "typst"], &["blog", "docs"]);
+        assert!(selection.matches(&profile(1, "typst", &["docs"])));
+        assert!(selection.matches(&profile(2, "pandoc", &["draft", "blog"])));
+    }
+
+    #[test]
+    fn tag_and_engine_must_both_match() {
+        let selection = selection(&[], &["pandoc"], &["blog"]);
+        assert!(selection.matches(&profile(1, "pandoc", &["blog"])));
+        assert!(!selection.matches(&profile(2, "typst", &["blog"])));
+        assert!(!selection.matches(&profile(3, "pandoc", &["docs"])));
+        assert!(!selection.matches(&profile(4, "pandoc", &[])));
+    }
+
+    #[test]
+    fn id_narrows_the_other_criteria() {
+        let selection = selection(&[2], &["pandoc"], &[]);
+        assert!(selection.matches(&profile(2, "pandoc", &[])));
+        assert!(!selection.matches(&profile(1, "pandoc", &[])));
+        assert!(!selection.matches(&profile(2, "typst", &[])));
+    }
+
+    #[test]
+    fn source_file_matcher_matches_extensions_and_globs() {
+        let mut profile = profile(1, "pandoc", &[]);
+        profile.source_file_extensions = vec![".md".to_string(), "*.tar.gz".to_string()];
+        let matcher = source_file_matcher(&profile).unwrap();
+        assert!(matcher.is_match("notes.md"));
+        assert!(matcher.is_match("backup.tar.gz"));
+        assert!(!matcher.is_match("notes.mdx"));
+        assert!(!matcher.is_match("backup.gz"));
+        assert!(!matcher.is_match("NOTES.MD"));
+    }
+
+    #[test]
+    fn source_file_matcher_ignores_case_when_asked() {
+        let mut profile = profile(1, "pandoc", &[]);
+        profile.source_file_extensions = vec!["md".to_string(), "draft-*.TXT".to_string()];
+        profile.case_insensitive = true;
+        let matcher = source_file_matcher(&profile).unwrap();
+        assert!(matcher.is_match("NOTES.MD"));
+        assert!(matcher.is_match("Draft-1.txt"));
+        assert!(!matcher.is_match("notes.txt"));
+    }
+
+    #[test]
+    fn source_file_matcher_rejects_invalid_globs() {
+        let mut profile = profile(1, "pandoc", &[]);
+        profile.source_file_extensions = vec!["*.{md".to_string()];
+        assert!(source_file_matcher(&profile).is_err());
+    }
+
+    #[test]
+    fn parse_target_splits_extension_and_quoted_options() {
+        let target = parse_target(r#"pdf --toc --metadata "title=My notes""#).unwrap();
+        assert_eq!(target.output_file_extension, "pdf");
+        assert_eq!(
+            target.options,
+            Some(vec![
+                "--toc".to_string(),
+                "--metadata".to_string(),
+                "title=My notes".to_string()
+            ])
+        );
+        assert_eq!(parse_target("  epub ").unwrap().options, None);
+        assert_eq!(parse_target(&format_target(&target)).unwrap(), target);
+    }
+
+    #[test]
+    fn parse_target_rejects_empty_and_unbalanced_input() {
+        assert!(matches!(parse_target(""), Err(TernError::InvalidTarget(_))));
+        assert!(matches!(
+            parse_target("pdf 'toc"),
+            Err(TernError::InvalidTarget(_))
+        ));
+    }
+
+    #[test]
+    fn check_targets_rejects_a_repeated_extension() {
+        let mut profile = profile(1, "pandoc", &[]);
+        profile.targets = Some(vec![parse_target("pdf").unwrap()]);
+        assert!(check_targets(&profile).is_ok());
+        profile.targets = Some(vec![parse_target("html --standalone").unwrap()]);
+        assert!(matches!(
+            check_targets(&profile),
+            Err(TernError::DuplicateTarget(extension)) if extension == "html"
+        ));
+    }
+
+    #[test]
+    fn fill_template_replaces_placeholders() {
+        let rendered = fill_template("{output_root}/{stem}-{ext}.{ext}", |placeholder| {
+            placeholder.to_uppercase()
+        });
+        assert_eq!(rendered.unwrap(), "OUTPUT_ROOT/STEM-EXT.EXT");
+    }
+
+    #[test]
+    fn fill_template_rejects_unbalanced_braces_and_unknown_placeholders() {
+        for template in [
+            "{output_root}/{stem",
+            "{output_root}/stem}",
+            "{output_root}/{{stem}}",
+            "{output_root}/{}",
+            "{output_root}/{title}",
+        ] {
+            assert!(
+                matches!(
+                    fill_template(template, |_| String::new()),
+                    Err(TernError::InvalidTemplate(_))
+                ),
+                "{template}"
+            );
+        }
+    }
+
+    #[test]
+    fn check_output_template_keeps_outputs_under_the_output_root() {
+        assert!(check_output_template(FLATTEN_TEMPLATE).is_ok());
+        for template in ["{path}/{stem}.{ext}", "{output_root}/../{stem}.{ext}"] {
+            assert!(matches!(
+                check_output_template(template),
+                Err(TernError::TemplateOutsideOutputRoot(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn render_output_template_lays_out_source_files() {
+        let profile = profile(1, "pandoc", &[]);
+        let target = parse_target("pdf").unwrap();
+        let render = |template, source_file| {
+            render_output_template(template, &profile, &target, Path::new(source_file))
+        };
+        assert_eq!(
+            render("{output_root}/{path}/{name}.{ext}", "/notes/a/b/c.md").unwrap(),
+            Path::new("/site/a/b/c.md.pdf")
+        );
+        // an empty {path} leaves no empty segment behind
+        assert_eq!(
+            render("{output_root}/{path}/{stem}.{ext}", "/notes/c.md").unwrap(),
+            Path::new("/site/c.pdf")
+        );
+        assert_eq!(
+            render(
+                "{output_root}/{parent_name}/{stem}.{source_ext}.{ext}",
+                "/notes/a/c.md"
+            )
+            .unwrap(),
+            Path::new("/site/a/c.md.pdf")
+        );
+    }
+
+    #[test]
+    fn render_output_template_accepts_output_roots_climbing_up() {
+        let mut profile = profile(1, "pandoc", &[]);
+        profile.output_root = "../site".to_string();
+        let target = parse_target("pdf").unwrap();
+        assert_eq!(
+            render_output_template(
+                FLATTEN_TEMPLATE,
+                &profile,
+                &target,
+                Path::new("/notes/a/c.md")
+            )
+            .unwrap(),
+            Path::new("../site/c.pdf")
+        );
+    }
+
+    #[test]
+    fn render_output_template_rejects_paths_outside_the_output_root() {
+        let profile = profile(1, "pandoc", &[]);
+        let target = parse_target("pdf").unwrap();
+        for template in ["{path}/{stem}.{ext}", "{output_root}/../{stem}.{ext}"] {
+            assert!(matches!(
+                render_output_template(template, &profile, &target, Path::new("/notes/a/c.md")),
+                Err(TernError::TemplateOutsideOutputRoot(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn civil_date_handles_leap_days() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+        assert_eq!(civil_date(1709164800), (2024, 2, 29));
+        assert_eq!(civil_date(1709251199), (2024, 2, 29));
+        assert_eq!(civil_date(1709251200), (2024, 3, 1));
+        assert_eq!(civil_date(951782400), (2000, 2, 29));
+        assert_eq!(civil_date(4107542400), (2100, 3, 1));
+    }
+
+    #[test]
+    fn parse_limit_reads_numbers_and_treats_empty_or_zero_as_none() {
+        assert_eq!(parse_limit("timeout", "").unwrap(), None);
+        assert_eq!(parse_limit("timeout", "  ").unwrap(), None);
+        assert_eq!(parse_limit("timeout", "0").unwrap(), None);
+        assert_eq!(parse_limit("timeout", " 30 ").unwrap(), Some(30));
+    }
+
+    #[test]
+    fn parse_limit_rejects_invalid_numbers() {
+        for value in ["-1", "1.5", "ten", "4294967296"] {
+            assert!(matches!(
+                parse_limit("timeout", value),
+                Err(TernError::InvalidLimit("timeout", invalid)) if invalid == value
+            ));
+        }
+    }
+}
@@ -1,25 +1,101 @@
-use crate::controller::{self, AgentEvent, AgentMessageBroker, Controller, ConverterArgs, Profile};
+use crate::controller::{
+    self, AgentEvent, AgentMessageBroker, Controller, ConversionResult, ConversionStatus,
+    ConverterArgs, OutputFormat, Profile, Response, Run, RunRecord, Target,
+};
+use crate::engine::{self, Converter, ConverterReturn, Job};
+use crate::error::TernError;
+use crate::host;
 use filetime::FileTime;
-use ignore::{gitignore::GitignoreBuilder, WalkBuilder};
+use globset::GlobSet;
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    DirEntry, WalkBuilder,
+};
 use indicatif::{ProgressBar, ProgressStyle};
-use mlua::{Function, Lua};
-use rayon::iter::{IntoParallelIterator, ParallelBridge, ParallelIterator};
+use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode};
+use rayon::{
+    iter::{IntoParallelRefIterator, ParallelIterator},
+    ThreadPool, ThreadPoolBuilder,
+};
+use serde_json::json;
 use std::{
-    fs,
-    path::Path,
+    collections::{BTreeMap, BTreeSet},
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    process,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc, Arc, Mutex, Once, OnceLock,
     },
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
+use tempfile::TempDir;
 use tokio::sync::mpsc::Sender;
 
+pub enum ConversionReason {
+    Forced,
+    MissingOutput,
+    Untracked,
+    RecipeChanged,
+    DependencyChanged,
+    Modified,
+    FailedLastRun,
+}
+
+impl fmt::Display for ConversionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            ConversionReason::Forced => "forced",
+            ConversionReason::MissingOutput => "missing output",
+            ConversionReason::Untracked => "untracked",
+            ConversionReason::RecipeChanged => "changed recipe",
+            ConversionReason::DependencyChanged => "changed dependency",
+            ConversionReason::Modified => "newer mtime",
+            ConversionReason::FailedLastRun => "failed last run",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// A source file converted to one target of its profile
+struct Conversion<'a> {
+    source_file: PathBuf,
+    output_file: PathBuf,
+    target: &'a Target,
+    fingerprint: &'a str,
+    // where the file was converted to last time, if it moved since (templated dates follow
+    // the source's mtime); removed once the new output is in place
+    previous_output: Option<PathBuf>,
+}
+
+/// Lifecycle steps of a run, printed as text or as NDJSON depending on the output format,
+/// or handed to an observer as JSON values
+enum RunEvent<'a> {
+    ProfileStarted(&'a Profile),
+    FilePlanned(&'a Profile, &'a Path, &'a Path, ConversionReason),
+    FileStarted(&'a Profile, &'a Path, &'a Path),
+    FileLogged(&'a Profile, &'a Path, &'a str),
+    FileFinished(&'a Profile, &'a ConversionResult),
+    FilePruned(&'a Profile, &'a Path, &'a [PathBuf], bool),
+    ProfileFinished(&'a Profile),
+    ProfileFailed(&'a Profile, &'a TernError),
+    RunSummary(&'a [ConversionResult], u32),
+    Watching(&'a [Profile]),
+    Error(&'a TernError),
+}
+
 pub struct ConverterFactory {
     tx: Option<Sender<AgentEvent>>,
     args: ConverterArgs,
     interrupt: Arc<AtomicBool>,
     spinner: ProgressBar,
+    results: Mutex<Vec<ConversionResult>>,
+    // profiles that could not run since the last run was stored
+    failed_profiles: AtomicU32,
+    failed_files: OnceLock<BTreeSet<(u8, String)>>,
+    observer: Option<mpsc::Sender<serde_json::Value>>,
+    // runs every conversion, `--jobs` threads at most
+    pool: ThreadPool,
 }
 
 impl ConverterFactory {
@@ -27,65 +103,724 @@ impl ConverterFactory {
         let interrupt = Arc::new(AtomicBool::new(false));
         let interrupt_clone = interrupt.clone();
         ctrlc::set_handler(move || {
+            // a second interruption does not wait for running conversions to stop
+            if interrupt_clone.swap(true, Ordering::SeqCst) {
+                eprintln!("\n\x1b[1;31mForce quitting\x1b[0m");
+                host::kill_running();
+                process::exit(130);
+            }
             eprintln!(
-                "\n\x1b[1;33mInterruption detected. Cancelling subsequent conversions...\x1b[0m"
+                "\n\x1b[1;33mInterruption detected. Stopping running conversions (Ctrl+C again to force quit)...\x1b[0m"
             );
-            interrupt_clone.store(true, Ordering::SeqCst);
         })
         .expect("Error setting Ctrl+C handler");
         Self {
             tx: Some(tx),
+            interrupt,
+            spinner: match args.output_format {
+                OutputFormat::Text => ProgressBar::new(1),
+                OutputFormat::Json => ProgressBar::hidden(),
+            },
+            pool: Self::thread_pool(&args),
             args,
+            results: Mutex::new(Vec::new()),
+            failed_profiles: AtomicU32::new(0),
+            failed_files: OnceLock::new(),
+            observer: None,
+        }
+    }
+
+    /// Builds a converter that sends its events (in their JSON form) to `observer` instead of
+    /// printing them, and is cancelled through `interrupt` rather than Ctrl+C
+    pub fn build_observed(
+        tx: Sender<AgentEvent>,
+        args: ConverterArgs,
+        interrupt: Arc<AtomicBool>,
+        observer: mpsc::Sender<serde_json::Value>,
+    ) -> Self {
+        Self {
+            tx: Some(tx),
             interrupt,
-            spinner: ProgressBar::new(1),
+            spinner: ProgressBar::hidden(),
+            pool: Self::thread_pool(&args),
+            args,
+            results: Mutex::new(Vec::new()),
+            failed_profiles: AtomicU32::new(0),
+            failed_files: OnceLock::new(),
+            observer: Some(observer),
         }
     }
 
+    fn thread_pool(args: &ConverterArgs) -> ThreadPool {
+        // 0 threads lets rayon pick the number of cores
+        ThreadPoolBuilder::new()
+            .num_threads(args.jobs.unwrap_or_default())
+            .build()
+            .expect("Error building the thread pool")
+    }
+
     pub async fn run(&self) {
+        self.convert().await;
+
+        let tx = self.tx.clone().unwrap();
+        Controller::send_quit_event(tx).await;
+    }
+
+    /// Runs every profile, leaving the session open for interfaces that start runs themselves
+    pub async fn convert(&self) {
+        let run: Result<(), TernError> = try {
+            let started_at = Self::unix_time();
+            let profiles = self.get_profiles().await?;
+            if profiles.is_empty() {
+                Err(TernError::NoMatchingProfile)?;
+            }
+            if self.args.retry_failed {
+                let failed_files = self.get_failed_files().await?;
+                self.failed_files.set(failed_files).unwrap();
+            }
+
+            if self.args.dry_run {
+                profiles
+                    .iter()
+                    .for_each(|profile| self.plan_profile(profile));
+            } else {
+                self.process_profiles(&profiles);
+                self.finish_run(started_at).await;
+            }
+            if self.args.prune && !self.interrupt.load(Ordering::SeqCst) {
+                for profile in &profiles {
+                    self.prune_profile(profile, self.args.dry_run).await;
+                }
+            }
+            if self.args.watch && !self.interrupt.load(Ordering::SeqCst) {
+                self.watch().await?;
+            }
+        };
+        if let Err(err) = run {
+            self.report(RunEvent::Error(&err));
+        }
+    }
+
+    /// Removes outputs and metadata of tracked source files that no longer exist
+    pub async fn prune(&self, dry_run: bool) {
+        match self.get_profiles().await {
+            Ok(profiles) => {
+                for profile in &profiles {
+                    self.prune_profile(profile, dry_run).await;
+                }
+            }
+            Err(err) => self.report(RunEvent::Error(&err)),
+        }
+
+        let tx = self.tx.clone().unwrap();
+        Controller::send_quit_event(tx).await;
+    }
+
+    /// Lists the most recent runs
+    pub async fn history(&self, limit: u32) {
+        let runs_arc: Response<Vec<RunRecord>> = Default::default();
+        let tx = self.tx.clone().unwrap();
+        Controller::send_get_runs_event(tx, Arc::clone(&runs_arc), limit).await;
+        match controller::wait_response(&runs_arc) {
+            Ok(runs) => runs.iter().for_each(|run| match self.args.output_format {
+                OutputFormat::Text => println!(
+                    "\x1b[1m#{}\x1b[0m {} -> {}  converted: {}, skipped: {}, failed: {}, interrupted: {}, timed out: {}, failed profiles: {}  [{}]",
+                    run.id,
+                    run.started_at,
+                    run.finished_at,
+                    run.converted,
+                    run.skipped,
+                    run.failed,
+                    run.interrupted,
+                    run.timed_out,
+                    run.failed_profiles,
+                    run.arguments
+                ),
+                OutputFormat::Json => println!(
+                    "{}",
+                    json!({
+                        "id": run.id,
+                        "started_at": run.started_at,
+                        "finished_at": run.finished_at,
+                        "arguments": run.arguments,
+                        "converted": run.converted,
+                        "skipped": run.skipped,
+                        "failed": run.failed,
+                        "interrupted": run.interrupted,
+                        "timed_out": run.timed_out,
+                        "failed_profiles": run.failed_profiles,
+                    })
+                ),
+            }),
+            Err(err) => self.report(RunEvent::Error(&err)),
+        }
+
+        let tx = self.tx.clone().unwrap();
+        Controller::send_quit_event(tx).await;
+    }
+
+    async fn get_failed_files(&self) -> Result<BTreeSet<(u8, String)>, TernError> {
+        let failed_files_arc: Response<Vec<(u8, String)>> = Default::default();
+        let tx = self.tx.clone().unwrap();
+        Controller::send_get_failed_files_event(tx, Arc::clone(&failed_files_arc)).await;
+        Ok(controller::wait_response(&failed_files_arc)?
+            .into_iter()
+            .collect())
+    }
+
+    async fn get_profiles(&self) -> Result<Vec<Profile>, TernError> {
         self.spinner.enable_steady_tick(Duration::from_millis(100));
         self.set_spinner_label("Loading resources");
         // stored data retrieval
-        let profiles_arc = Default::default();
-        let tx = self.tx.clone().unwrap();
-        Controller::send_get_profiles_event(tx, Arc::clone(&profiles_arc)).await;
-        let (lock, cvar) = &*profiles_arc;
-        let profiles = lock.lock().unwrap();
-        drop(cvar.wait(profiles).unwrap());
+        let profiles = self.fetch_profiles().await;
         self.spinner.finish();
+        profiles
+    }
 
-        self.process_profiles(profiles_arc);
+    /// Retrieves the stored profiles the run is restricted to
+    async fn fetch_profiles(&self) -> Result<Vec<Profile>, TernError> {
+        let profiles_arc: Response<Vec<Profile>> = Default::default();
+        let tx = self.tx.clone().unwrap();
+        Controller::send_get_profiles_event(tx, Arc::clone(&profiles_arc)).await;
+        Ok(controller::wait_response(&profiles_arc)?
+            .into_iter()
+            .filter(|profile| self.args.selection.matches(profile))
+            .collect())
+    }
 
+    /// Reports the results recorded since `started_at` and stores them as a run
+    async fn finish_run(&self, started_at: i64) {
+        let failed_profiles = self.failed_profiles.swap(0, Ordering::SeqCst);
+        self.report(RunEvent::RunSummary(
+            &self.results.lock().unwrap(),
+            failed_profiles,
+        ));
+        let run = Run {
+            started_at,
+            finished_at: Self::unix_time(),
+            arguments: env::args().skip(1).collect::<Vec<_>>().join(" "),
+            results: std::mem::take(&mut *self.results.lock().unwrap()),
+            failed_profiles,
+        };
         let tx = self.tx.clone().unwrap();
-        Controller::send_quit_event(tx).await;
+        Controller::send_store_run_event(tx, run).await;
     }
 
-    fn process_profiles(&self, profiles: Arc<(Mutex<Vec<Profile>>, Condvar)>) {
-        let lua = Lua::new();
-        let profiles = Arc::try_unwrap(profiles).unwrap().0.into_inner().unwrap();
-        if self.args.concurrent_profiles {
-            profiles.into_par_iter().for_each(|profile| {
-                self.process_profile(&profile, &lua);
-            });
-        } else {
-            profiles.into_iter().for_each(|profile| {
-                self.process_profile(&profile, &lua);
-            });
+    /// Converts files of the selected profiles as they change, until interrupted
+    async fn watch(&self) -> Result<(), TernError> {
+        let (events_tx, events_rx) = mpsc::channel();
+        let mut debouncer = new_debouncer(Duration::from_millis(500), events_tx)?;
+        let profiles = self.fetch_profiles().await?;
+        // event paths are absolute, tracked paths are relative to the working directory
+        let mut roots = Vec::new();
+        for profile in &profiles {
+            let root = fs::canonicalize(&profile.source_root)?;
+            debouncer.watcher().watch(&root, RecursiveMode::Recursive)?;
+            roots.push((profile.id, root));
+        }
+        self.report(RunEvent::Watching(&profiles));
+        // directories of dependencies outside of every source root, watched as they get tracked
+        let mut dependency_dirs = BTreeSet::new();
+        let mut tracked_profiles = Some(profiles);
+        while !self.interrupt.load(Ordering::SeqCst) {
+            for dir in tracked_profiles
+                .take()
+                .map(|profiles| Self::dependency_dirs(&profiles))
+                .unwrap_or_default()
+            {
+                if !roots.iter().any(|(_, root)| dir.starts_with(root))
+                    && dependency_dirs.insert(dir.clone())
+                {
+                    // a directory removed since its files were read has nothing left to watch
+                    let _ = debouncer.watcher().watch(&dir, RecursiveMode::NonRecursive);
+                }
+            }
+            let events = match events_rx.recv_timeout(Duration::from_millis(200)) {
+                Ok(events) => events?,
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+            let started_at = Self::unix_time();
+            let paths: Vec<&Path> = events.iter().map(|event| event.path.as_path()).collect();
+            // tracked files change with every batch
+            for profile in &self.fetch_profiles().await? {
+                let Some((_, root)) = roots.iter().find(|(id, _)| *id == profile.id) else {
+                    continue;
+                };
+                self.process_changes(profile, root, &paths).await;
+            }
+            // batches that only skipped files are not worth a run
+            let converted_any = self
+                .results
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|result| result.status != ConversionStatus::Skipped);
+            if converted_any || self.failed_profiles.load(Ordering::SeqCst) > 0 {
+                self.finish_run(started_at).await;
+            } else {
+                self.results.lock().unwrap().clear();
+            }
+            // conversions of the batch may have read new dependencies
+            tracked_profiles = Some(self.fetch_profiles().await?);
+        }
+        Ok(())
+    }
+
+    /// Parent directories of the dependencies tracked by the profiles
+    fn dependency_dirs(profiles: &[Profile]) -> BTreeSet<PathBuf> {
+        profiles
+            .iter()
+            .filter_map(|profile| profile.metadata.as_ref())
+            .flat_map(|tracked_files| tracked_files.values())
+            .flat_map(|tracked_targets| tracked_targets.values())
+            .flat_map(|tracked| tracked.dependencies.keys())
+            .filter_map(|dependency| Path::new(dependency).parent())
+            .filter(|dir| dir.is_absolute())
+            .map(Path::to_path_buf)
+            .collect()
+    }
+
+    /// Converts changed source files of a profile and the sources that read changed files;
+    /// deleted sources are pruned when asked to. `paths` are the absolute paths that changed,
+    /// `root` the canonical source root of the profile
+    async fn process_changes(&self, profile: &Profile, root: &Path, paths: &[&Path]) {
+        let source_matcher = match controller::source_file_matcher(profile) {
+            Ok(source_matcher) => source_matcher,
+            Err(err) => {
+                self.fail_profile(profile, &err);
+                return;
+            }
+        };
+        let ignore_matcher = match Self::ignore_matcher(profile) {
+            Ok(ignore_matcher) => ignore_matcher,
+            Err(err) => {
+                self.fail_profile(profile, &err);
+                return;
+            }
+        };
+        let tracked_files = profile.metadata.as_ref();
+        let mut source_files = BTreeSet::new();
+        for &absolute_path in paths {
+            // sources are tracked relative to the working directory, like the source root
+            let path = absolute_path
+                .strip_prefix(root)
+                .ok()
+                .map(|relative| (relative, Path::new(&profile.source_root).join(relative)));
+            if let Some(tracked_files) = tracked_files {
+                // dependencies are tracked canonical, or relative like sources by older versions
+                let spellings = [
+                    absolute_path.to_str(),
+                    path.as_ref().and_then(|(_, path)| path.to_str()),
+                ];
+                source_files.extend(
+                    tracked_files
+                        .iter()
+                        .filter(|(_, targets)| {
+                            targets.values().any(|tracked| {
+                                spellings
+                                    .iter()
+                                    .flatten()
+                                    .any(|spelling| tracked.dependencies.contains_key(*spelling))
+                            })
+                        })
+                        .map(|(source_file, _)| PathBuf::from(source_file)),
+                );
+            }
+            let Some((relative, path)) = path else {
+                continue;
+            };
+            let hidden = self.args.hidden
+                && relative
+                    .components()
+                    .any(|component| component.as_os_str().to_string_lossy().starts_with('.'));
+            if !hidden
+                && Self::is_source_file(&source_matcher, &path)
+                && !ignore_matcher
+                    .matched_path_or_any_parents(&path, false)
+                    .is_ignore()
+            {
+                source_files.insert(path);
+            }
+        }
+        let (existing, deleted): (Vec<PathBuf>, Vec<PathBuf>) = source_files
+            .into_iter()
+            .partition(|source_file| source_file.is_file());
+        if self.args.prune {
+            for source_file in deleted.iter().filter(|source_file| {
+                tracked_files
+                    .is_some_and(|tracked| tracked.contains_key(source_file.to_str().unwrap()))
+            }) {
+                self.prune_file(profile, source_file, false).await;
+            }
         }
+        if !existing.is_empty() {
+            self.pool
+                .install(|| self.process_files(profile, existing.into_iter(), false));
+        }
+    }
+
+    fn process_profiles(&self, profiles: &[Profile]) {
+        self.pool.install(|| {
+            if self.args.concurrent_profiles {
+                profiles.par_iter().for_each(|profile| {
+                    self.process_profile(profile);
+                });
+            } else {
+                profiles.iter().for_each(|profile| {
+                    self.process_profile(profile);
+                });
+            }
+        });
     }
 
-    fn process_profile(&self, profile: &Profile, lua: &Lua) {
+    fn process_profile(&self, profile: &Profile) {
+        Self::sweep_staging_dirs(Path::new(&profile.output_root));
+        match self.walk_profile(profile) {
+            Ok(entries) => self.process_files(profile, entries.map(DirEntry::into_path), true),
+            Err(err) => self.fail_profile(profile, &err),
+        }
+    }
+
+    /// Converts the source files of a profile that need it; with `report_idle` unset, the profile
+    /// is only reported once one of its files is about to be converted
+    fn process_files(
+        &self,
+        profile: &Profile,
+        source_files: impl Iterator<Item = PathBuf> + Send,
+        report_idle: bool,
+    ) {
         if self.interrupt.load(Ordering::SeqCst) {
             return;
         }
-        println!("\x1b[1mRunning '{}' engine\x1b[0m", profile.engine);
-        // ignore patterns
-        let mut ignore_builder = GitignoreBuilder::new(&profile.source_root);
-        if let Some(ignore_pattern) = &profile.ignore_patterns {
-            ignore_pattern.iter().for_each(|glob| {
-                ignore_builder.add_line(None, glob).unwrap();
-            });
+        let started = Once::new();
+        let start = || started.call_once(|| self.report(RunEvent::ProfileStarted(profile)));
+        if report_idle {
+            start();
+        }
+        // recipe fingerprint of every target
+        let targets = Self::fingerprint_targets(profile);
+        // resolve the converter by engine name; a broken script fails its profile only
+        let converter = match engine::load_converter(profile) {
+            Ok(converter) => converter,
+            Err(err) => {
+                self.fail_profile(profile, &err);
+                return;
+            }
+        };
+        // every output is laid out before anything is converted
+        let conversions = match Self::lay_out(profile, &targets, source_files) {
+            Ok(conversions) => conversions,
+            Err(err) => {
+                self.fail_profile(profile, &err);
+                return;
+            }
+        };
+        // iterate over files, converting each of their targets
+        let queue = Mutex::new(conversions.into_iter().filter(|conversion| {
+            match self.conversion_reason(profile, conversion) {
+                Some(reason) => {
+                    start();
+                    self.report(RunEvent::FilePlanned(
+                        profile,
+                        &conversion.source_file,
+                        &conversion.output_file,
+                        reason,
+                    ));
+                    true
+                }
+                None => {
+                    self.record(
+                        profile,
+                        ConversionResult::new(
+                            profile.id,
+                            conversion.source_file.clone(),
+                            conversion.output_file.clone(),
+                            ConversionStatus::Skipped,
+                        ),
+                    );
+                    false
+                }
+            }
+        }));
+        // workers of the pool drain the queue, at most `max_parallel` of them for this profile
+        let next = || queue.lock().unwrap().next();
+        let workers = profile
+            .max_parallel
+            .map_or(usize::MAX, |max_parallel| max_parallel as usize)
+            .min(rayon::current_num_threads());
+        rayon::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|_| {
+                    while let Some(conversion) = next() {
+                        self.convert_file(profile, converter.as_ref(), conversion);
+                    }
+                });
+            }
+        });
+        if started.is_completed() {
+            self.report(RunEvent::ProfileFinished(profile));
+        }
+    }
+
+    /// Converts a source file to one target and records the outcome
+    fn convert_file(&self, profile: &Profile, converter: &dyn Converter, conversion: Conversion) {
+        // define source_file, output_file
+        let Conversion {
+            source_file,
+            output_file,
+            target,
+            fingerprint,
+            previous_output,
+        } = conversion;
+        let source_file = source_file.as_path();
+        if self.interrupt.load(Ordering::SeqCst) {
+            self.record(
+                profile,
+                ConversionResult::new(
+                    profile.id,
+                    source_file.to_path_buf(),
+                    output_file,
+                    ConversionStatus::Interrupted,
+                ),
+            );
+            return;
+        }
+        // notify conversion has started
+        self.report(RunEvent::FileStarted(profile, source_file, &output_file));
+        // create output path, run converter with raw paths; errors fail the file only
+        let start = Instant::now();
+        let timeout = profile
+            .timeout
+            .map(|timeout| Duration::from_secs(timeout.into()));
+        // converters that write the output themselves, whatever they were told, change it
+        let modified = || {
+            fs::metadata(&output_file)
+                .and_then(|metadata| metadata.modified())
+                .ok()
         };
-        let ignore_matcher = ignore_builder.build().unwrap();
+        let previously_modified = modified();
+        let staging = Self::staging_dir(&output_file);
+        let staged_file = staging
+            .as_ref()
+            .map(|staging| staging.path().join(output_file.file_name().unwrap()));
+        let mut returned = match &staged_file {
+            Ok(staged_file) => converter.convert(&Job {
+                profile,
+                source_file,
+                output_file: staged_file,
+                options: target.options.as_deref().unwrap_or_default(),
+                deadline: timeout.map(|timeout| start + timeout),
+                interrupt: &self.interrupt,
+            }),
+            Err(err) => ConverterReturn::failed(err.to_string()),
+        };
+        for message in returned.messages.drain(..) {
+            self.report(RunEvent::FileLogged(profile, source_file, &message));
+        }
+        // only conversions stopped at their deadline time out, late ones that completed count
+        let timed_out = timeout.filter(|_| returned.timed_out);
+        if let Some(timeout) = timed_out {
+            returned.output = Some(format!(
+                "timed out after {:?}\n{}",
+                timeout,
+                returned.output.unwrap_or_default()
+            ));
+        }
+        // outputs are moved into place whole, so a partial one never looks up to date; the other
+        // files the converter wrote next to it (source maps, chunks) are moved along
+        if let (Ok(staging), Ok(staged_file), true, None) =
+            (&staging, &staged_file, returned.success, timed_out)
+        {
+            if !staged_file.exists()
+                && modified().is_none_or(|modified| Some(modified) == previously_modified)
+            {
+                returned.success = false;
+                returned.output = Some(format!(
+                    "The converter did not write {}\n{}",
+                    output_file.to_str().unwrap(),
+                    returned.output.unwrap_or_default()
+                ));
+            } else if let Err(err) =
+                Self::move_staged(staging.path(), output_file.parent().unwrap(), staged_file)
+            {
+                returned.success = false;
+                returned.output = Some(format!(
+                    "Could not move {} into place: {}",
+                    output_file.to_str().unwrap(),
+                    err
+                ));
+            }
+        }
+        drop(staging);
+        let interrupted = self.interrupt.load(Ordering::SeqCst);
+        let result = ConversionResult {
+            profile_id: profile.id,
+            source_file: source_file.to_path_buf(),
+            output_file: output_file.clone(),
+            status: match (timed_out, returned.success, interrupted) {
+                (Some(_), _, _) => ConversionStatus::TimedOut,
+                (None, true, _) => ConversionStatus::Converted,
+                (None, false, true) => ConversionStatus::Interrupted,
+                (None, false, false) => ConversionStatus::Failed,
+            },
+            duration: start.elapsed(),
+            exit_code: returned.exit_code,
+            output: returned.output,
+        };
+        // dependency paths are relative to the source file, they are tracked canonical so that
+        // every spelling of a file matches the paths watch events carry
+        let dependencies = returned
+            .dependencies
+            .into_iter()
+            .map(|dependency| {
+                let dependency = source_file.parent().unwrap().join(dependency);
+                fs::canonicalize(&dependency).unwrap_or(dependency)
+            })
+            .collect();
+        // notify conversion status
+        let converted = result.status == ConversionStatus::Converted;
+        self.record(profile, result);
+        if let Some(previous_output) = previous_output.filter(|_| converted) {
+            match Self::remove_output(Path::new(&profile.output_root), &previous_output) {
+                Ok(()) => self.report(RunEvent::FilePruned(
+                    profile,
+                    source_file,
+                    &[previous_output],
+                    false,
+                )),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                Err(err) => eprintln!(
+                    "Could not remove {}: {}",
+                    previous_output.to_str().unwrap(),
+                    err
+                ),
+            }
+        }
+        // update metadata of successful conversions
+        if converted {
+            let tx = self.tx.clone();
+            let _runtime_guard = controller::get_runtime_handle().enter();
+            let message_handle = async {
+                Controller::send_update_metadata_event(
+                    tx.unwrap(),
+                    (
+                        source_file.to_path_buf(),
+                        profile.id,
+                        target.output_file_extension.clone(),
+                        output_file,
+                        fingerprint.to_string(),
+                        dependencies,
+                    ),
+                )
+                .await;
+            };
+            futures::executor::block_on(message_handle);
+        }
+    }
+
+    fn plan_profile(&self, profile: &Profile) {
+        let entries = match self.walk_profile(profile) {
+            Ok(entries) => entries,
+            Err(err) => return self.fail_profile(profile, &err),
+        };
+        self.report(RunEvent::ProfileStarted(profile));
+        let targets = Self::fingerprint_targets(profile);
+        let conversions = match Self::lay_out(profile, &targets, entries.map(DirEntry::into_path)) {
+            Ok(conversions) => conversions,
+            Err(err) => return self.fail_profile(profile, &err),
+        };
+        for conversion in &conversions {
+            if let Some(reason) = self.conversion_reason(profile, conversion) {
+                self.report(RunEvent::FilePlanned(
+                    profile,
+                    &conversion.source_file,
+                    &conversion.output_file,
+                    reason,
+                ));
+            }
+        }
+        self.report(RunEvent::ProfileFinished(profile));
+    }
+
+    /// Pairs every target of a profile with its recipe fingerprint
+    fn fingerprint_targets(profile: &Profile) -> Vec<(Target, String)> {
+        controller::profile_targets(profile)
+            .into_iter()
+            .map(|target| {
+                let fingerprint = controller::target_fingerprint(profile, &target);
+                (target, fingerprint)
+            })
+            .collect()
+    }
+
+    /// Computes the output file of every source file and target, failing when two of them
+    /// would overwrite each other
+    fn lay_out<'a>(
+        profile: &Profile,
+        targets: &'a [(Target, String)],
+        source_files: impl Iterator<Item = PathBuf>,
+    ) -> Result<Vec<Conversion<'a>>, TernError> {
+        let mut sources_by_output: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+        let mut conversions = Vec::new();
+        for source_file in source_files {
+            for (target, fingerprint) in targets {
+                let output_file = Self::output_file(profile, target, &source_file)?;
+                if let Some(other_source_file) =
+                    sources_by_output.insert(output_file.clone(), source_file.clone())
+                {
+                    Err(TernError::OutputCollision(
+                        output_file.clone(),
+                        other_source_file,
+                        source_file.clone(),
+                    ))?;
+                }
+                conversions.push(Conversion {
+                    source_file: source_file.clone(),
+                    output_file,
+                    target,
+                    fingerprint,
+                    previous_output: None,
+                });
+            }
+        }
+        // a previous output is left alone when another file is (or was) converted to it
+        let mut tracked_outputs: BTreeMap<&str, usize> = BTreeMap::new();
+        for tracked_targets in profile.metadata.iter().flat_map(BTreeMap::values) {
+            for output_file in tracked_targets
+                .values()
+                .filter_map(|tracked| tracked.output_file.as_deref())
+            {
+                *tracked_outputs.entry(output_file).or_default() += 1;
+            }
+        }
+        for conversion in &mut conversions {
+            conversion.previous_output = profile
+                .metadata
+                .as_ref()
+                .and_then(|tracked_files| tracked_files.get(conversion.source_file.to_str()?))
+                .and_then(|tracked_targets| {
+                    tracked_targets.get(&conversion.target.output_file_extension)
+                })
+                .and_then(|tracked| tracked.output_file.as_deref())
+                .filter(|previous_output| tracked_outputs.get(previous_output) == Some(&1))
+                .map(PathBuf::from)
+                .filter(|previous_output| {
+                    *previous_output != conversion.output_file
+                        && !sources_by_output.contains_key(previous_output)
+                        && previous_output.starts_with(&profile.output_root)
+                });
+        }
+        Ok(conversions)
+    }
+
+    /// Walks the source root of a profile, yielding files not ignored and with a source extension
+    fn walk_profile(
+        &self,
+        profile: &Profile,
+    ) -> Result<impl Iterator<Item = DirEntry> + Send, TernError> {
+        let source_matcher = controller::source_file_matcher(profile)?;
+        let ignore_matcher = Self::ignore_matcher(profile)?;
         // walker configuration
         let mut walk_builder = WalkBuilder::new(&profile.source_root);
         walk_builder
@@ -98,125 +833,481 @@ impl ConverterFactory {
             .git_exclude(false)
             .filter_entry(move |entry| {
                 !ignore_matcher
-                    .matched(entry.path(), entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false))
+                    .matched(
+                        entry.path(),
+                        entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false),
+                    )
                     .is_ignore()
             });
-        // load lua converter
-        let converter: Function = lua
-            .load(controller::get_converters_dir().join(&profile.engine))
-            .eval()
-            .unwrap();
-        // iterate over files
-        walk_builder
+        Ok(walk_builder
             .build()
             .filter_map(|entry| {
                 entry
                     .map_err(|err| eprintln!("Error processing directory entry: {}", err))
                     .ok()
             })
-            .filter(|entry| {
-                entry
-                    .path()
-                    .extension()
-                    .and_then(|ext| ext.to_str())
-                    .is_some_and(|ext_str| ext_str == profile.source_file_extension)
+            .filter(move |entry| Self::is_source_file(&source_matcher, entry.path())))
+    }
+
+    /// Matches the ignore patterns of a profile, relative to its source root
+    fn ignore_matcher(profile: &Profile) -> Result<Gitignore, TernError> {
+        let mut ignore_builder = GitignoreBuilder::new(&profile.source_root);
+        for glob in profile.ignore_patterns.iter().flatten() {
+            ignore_builder.add_line(None, glob)?;
+        }
+        Ok(ignore_builder.build()?)
+    }
+
+    fn is_source_file(source_matcher: &GlobSet, path: &Path) -> bool {
+        path.file_name()
+            .is_some_and(|file_name| source_matcher.is_match(file_name))
+    }
+
+    /// Returns why a source file has to be converted to a target, if it has to
+    fn conversion_reason(
+        &self,
+        profile: &Profile,
+        conversion: &Conversion,
+    ) -> Option<ConversionReason> {
+        let Conversion {
+            source_file,
+            output_file,
+            target,
+            fingerprint,
+            ..
+        } = conversion;
+        // only files that failed in the latest run are retried
+        if let Some(failed_files) = self.failed_files.get() {
+            return failed_files
+                .contains(&(profile.id, source_file.to_str().unwrap().to_string()))
+                .then_some(ConversionReason::FailedLastRun);
+        }
+        if self.args.force {
+            return Some(ConversionReason::Forced);
+        }
+        if !output_file.exists() {
+            return Some(ConversionReason::MissingOutput);
+        }
+        let Some(tracked) = profile
+            .metadata
+            .as_ref()
+            .and_then(|tracked_files| tracked_files.get(source_file.to_str().unwrap()))
+            .and_then(|tracked_targets| tracked_targets.get(&target.output_file_extension))
+        else {
+            return Some(ConversionReason::Untracked);
+        };
+        // converter script, options or output extension changed
+        if tracked.fingerprint.as_deref() != Some(*fingerprint) {
+            return Some(ConversionReason::RecipeChanged);
+        }
+        // a dependency reported by the converter changed or disappeared
+        if tracked
+            .dependencies
+            .iter()
+            .any(|(dependency, tracked_mtime)| {
+                fs::metadata(dependency).ok().is_none_or(|metadata| {
+                    *tracked_mtime < FileTime::from_last_modification_time(&metadata).unix_seconds()
+                })
             })
-            .filter(|entry| {
-                if self.args.force {
-                    return true;
-                }
-                if !Path::new(&profile.output_root)
-                    .join(
-                        entry
-                            .path()
-                            .strip_prefix(&profile.source_root)
-                            .unwrap()
-                            .parent()
-                            .unwrap(),
-                    )
-                    .join(entry.file_name())
-                    .with_extension(&profile.output_file_extension)
-                    .exists()
+        {
+            return Some(ConversionReason::DependencyChanged);
+        }
+        let modified = fs::metadata(source_file).ok().is_some_and(|metadata| {
+            tracked.mtime < FileTime::from_last_modification_time(&metadata).unix_seconds()
+        });
+        // mtime changes without content changes (checkout, touch, copy) are skipped
+        let changed = !modified
+            || !self.args.content_hash
+            || tracked.hash.is_none()
+            || tracked.hash != controller::hash_file(source_file);
+        // and the new mtime is kept, so the file is not hashed again next run
+        if modified && !changed && !self.args.dry_run {
+            self.refresh_mtime(profile, source_file, target);
+        }
+        (modified && changed).then_some(ConversionReason::Modified)
+    }
+
+    fn refresh_mtime(&self, profile: &Profile, source_file: &Path, target: &Target) {
+        let tx = self.tx.clone();
+        let _runtime_guard = controller::get_runtime_handle().enter();
+        futures::executor::block_on(Controller::send_refresh_mtime_event(
+            tx.unwrap(),
+            (
+                source_file.to_path_buf(),
+                profile.id,
+                target.output_file_extension.clone(),
+            ),
+        ));
+    }
+
+    async fn prune_profile(&self, profile: &Profile, dry_run: bool) {
+        let Some(tracked_files) = &profile.metadata else {
+            return;
+        };
+        for source_file in tracked_files.keys().map(Path::new) {
+            if !source_file.exists() {
+                self.prune_file(profile, source_file, dry_run).await;
+            }
+        }
+    }
+
+    /// Removes the outputs and metadata of a tracked source file that no longer exists
+    async fn prune_file(&self, profile: &Profile, source_file: &Path, dry_run: bool) {
+        let output_root = Path::new(&profile.output_root);
+        let tracked_targets = profile
+            .metadata
+            .as_ref()
+            .and_then(|tracked_files| tracked_files.get(source_file.to_str().unwrap()));
+        // outputs are found where they were written, templates may depend on the source file
+        let stale_outputs: Vec<PathBuf> = controller::profile_targets(profile)
+            .iter()
+            .filter_map(|target| {
+                match tracked_targets
+                    .and_then(|tracked| tracked.get(&target.output_file_extension))
+                    .and_then(|tracked| tracked.output_file.as_ref())
                 {
-                    return true;
-                }
-                let source_file = entry.path();
-                match &profile.metadata {
-                    Some(tracked_file) => tracked_file
-                        .get(source_file.to_str().unwrap())
-                        .map(|tracked_mtime| {
-                            fs::metadata(source_file).ok().is_some_and(|metadata| {
-                                *tracked_mtime
-                                    < FileTime::from_last_modification_time(&metadata)
-                                        .unix_seconds()
-                            })
-                        })
-                        .unwrap_or(true),
-                    None => true,
+                    Some(output_file) => Some(PathBuf::from(output_file)),
+                    None => Self::output_file(profile, target, source_file).ok(),
                 }
             })
-            .par_bridge()
-            .for_each(|entry| {
-                if self.interrupt.load(Ordering::SeqCst) {
-                    return;
-                }
-                // create output path
-                let output_path = Path::new(&profile.output_root).join(
-                    entry
-                        .path()
-                        .strip_prefix(&profile.source_root)
-                        .unwrap()
-                        .parent()
-                        .unwrap(),
+            .filter(|output_file| output_file.exists())
+            .collect();
+        if dry_run {
+            self.report(RunEvent::FilePruned(
+                profile,
+                source_file,
+                &stale_outputs,
+                dry_run,
+            ));
+            return;
+        }
+        for stale_output in &stale_outputs {
+            if let Err(err) = Self::remove_output(output_root, stale_output) {
+                eprintln!(
+                    "Could not remove {}: {}",
+                    stale_output.to_str().unwrap(),
+                    err
                 );
-                fs::create_dir_all(&output_path).unwrap();
-                // define source_file, output_file
-                let source_file = entry.path();
-                let output_file = output_path
-                    .join(entry.file_name())
-                    .with_extension(&profile.output_file_extension);
-                // notify conversion has started
-                println!("Processing: {}", source_file.to_str().unwrap());
-                let escape_shell_chars = |path: &str| -> String {
-                    path.replace("&", "\\&")
-                        .replace(";", "\\;")
-                        .replace("|", "\\|")
-                        .replace(">", "\\>")
-                        .replace("<", "\\<")
-                        .replace("`", "\\`")
-                        .replace("$", "\\$")
-                        .replace("(", "\\(")
-                        .replace(")", "\\)")
-                        .replace(" ", "\\ ")
-                };
-                // run converter
-                let result = converter
-                    .call::<bool>((
-                        escape_shell_chars(source_file.to_str().unwrap()),
-                        escape_shell_chars(output_file.to_str().unwrap()),
-                        profile.options.clone().unwrap_or(vec!["".to_string()]),
-                    ))
-                    .unwrap();
-                // notify conversion status
+                return;
+            }
+        }
+        self.report(RunEvent::FilePruned(
+            profile,
+            source_file,
+            &stale_outputs,
+            dry_run,
+        ));
+        let tx = self.tx.clone().unwrap();
+        Controller::send_remove_metadata_event(tx, (source_file.to_path_buf(), profile.id)).await;
+    }
+
+    /// Removes an output along with the directories it leaves empty, up to the output root
+    fn remove_output(output_root: &Path, output_file: &Path) -> io::Result<()> {
+        fs::remove_file(output_file)?;
+        let mut directory = output_file.parent();
+        while let Some(dir) = directory {
+            if dir == output_root || !dir.starts_with(output_root) || fs::remove_dir(dir).is_err() {
+                break;
+            }
+            directory = dir.parent();
+        }
+        Ok(())
+    }
+
+    /// Renders the output template of the profile, or mirrors the source file's location under
+    /// the source root into the output root
+    fn output_file(
+        profile: &Profile,
+        target: &Target,
+        source_file: &Path,
+    ) -> Result<PathBuf, TernError> {
+        if let Some(output_template) = &profile.output_template {
+            return controller::render_output_template(
+                output_template,
+                profile,
+                target,
+                source_file,
+            );
+        }
+        // files tracked before the source root was edited are not under it anymore
+        let relative = source_file
+            .strip_prefix(&profile.source_root)
+            .map_err(|_| {
+                TernError::OutsideSourceRoot(source_file.to_path_buf(), profile.source_root.clone())
+            })?;
+        Ok(Path::new(&profile.output_root)
+            .join(relative)
+            .with_extension(&target.output_file_extension))
+    }
+
+    // where a file is converted before being moved into place: a hidden directory of its own
+    // next to the output, so that it is renamed within one filesystem and keeps its name
+    fn staging_dir(output_file: &Path) -> io::Result<TempDir> {
+        let output_dir = output_file.parent().unwrap();
+        fs::create_dir_all(output_dir)?;
+        // named after the run, so that the next runs tell the ones left by a killed run apart
+        tempfile::Builder::new()
+            .prefix(&format!(".tern-{}-", process::id()))
+            .tempdir_in(output_dir)
+    }
+
+    /// Moves what a converter wrote to a staging directory into the output directory, merging
+    /// directories that already exist there; `last` is moved once everything else is in place
+    fn move_staged(staging: &Path, output_dir: &Path, last: &Path) -> io::Result<()> {
+        let mut entries = fs::read_dir(staging)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.path() == last);
+        for entry in entries {
+            let destination = output_dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() && destination.is_dir() {
+                Self::move_staged(&entry.path(), &destination, last)?;
+            } else {
+                fs::rename(entry.path(), destination)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the staging directories that runs which were killed left under a directory
+    fn sweep_staging_dirs(dir: &Path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if !entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                continue;
+            }
+            let name = entry.file_name();
+            let run = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(".tern-"))
+                .and_then(|name| name.split_once('-'))
+                .and_then(|(pid, _)| pid.parse::<u32>().ok());
+            match run {
+                Some(pid) if !host::process_alive(pid) => {
+                    let _ = fs::remove_dir_all(entry.path());
+                }
+                // still converting, in this run or another one
+                Some(_) => (),
+                None => Self::sweep_staging_dirs(&entry.path()),
+            }
+        }
+    }
+
+    /// Reports a profile that could not run, counted along with the run
+    fn fail_profile(&self, profile: &Profile, err: &TernError) {
+        self.failed_profiles.fetch_add(1, Ordering::SeqCst);
+        self.report(RunEvent::ProfileFailed(profile, err));
+    }
+
+    fn record(&self, profile: &Profile, result: ConversionResult) {
+        if result.status != ConversionStatus::Skipped {
+            self.report(RunEvent::FileFinished(profile, &result));
+        }
+        self.results.lock().unwrap().push(result);
+    }
+
+    fn report(&self, event: RunEvent) {
+        if let Some(observer) = &self.observer {
+            // the observer may have stopped listening, the run goes on regardless
+            let _ = observer.send(Self::serialize_event(event));
+            return;
+        }
+        match self.args.output_format {
+            OutputFormat::Text => self.print_event(event),
+            OutputFormat::Json => println!("{}", Self::serialize_event(event)),
+        }
+    }
+
+    fn print_event(&self, event: RunEvent) {
+        match event {
+            RunEvent::ProfileStarted(profile) if self.args.dry_run => {
+                println!("\x1b[1mPlanning '{}' engine\x1b[0m", profile.engine)
+            }
+            RunEvent::ProfileStarted(profile) => {
+                println!("\x1b[1mRunning '{}' engine\x1b[0m", profile.engine)
+            }
+            RunEvent::FilePlanned(_, source_file, output_file, reason) if self.args.dry_run => {
+                println!(
+                    "Would convert [{}]: {} -> {}",
+                    reason,
+                    source_file.to_str().unwrap(),
+                    output_file.to_str().unwrap()
+                )
+            }
+            RunEvent::FileStarted(_, source_file, output_file) => println!(
+                "Processing: {} -> {}",
+                source_file.to_str().unwrap(),
+                output_file.to_str().unwrap()
+            ),
+            RunEvent::FileLogged(_, source_file, message) => {
                 eprintln!(
-                    "\x1b[2mSuccess [{}]: {}\x1b[0m",
+                    "\x1b[2m[{}] {}\x1b[0m",
                     source_file.to_str().unwrap(),
-                    result
-                );
-                // update metadata of successful conversions
-                if result {
-                    let tx = self.tx.clone();
-                    let _runtime_guard = controller::get_runtime_handle().enter();
-                    let message_handle = async {
-                        Controller::send_update_metadata_event(
-                            tx.unwrap(),
-                            (source_file.to_path_buf(), profile.id),
-                        )
-                        .await;
-                    };
-                    futures::executor::block_on(message_handle);
+                    message
+                )
+            }
+            RunEvent::FileFinished(_, result) if result.status != ConversionStatus::Interrupted => {
+                eprintln!(
+                    "\x1b[2mFinished [{}]: {} in {:.2?}\x1b[0m",
+                    result.output_file.to_str().unwrap(),
+                    result.status,
+                    result.duration
+                )
+            }
+            RunEvent::FilePruned(_, source_file, output_files, true) => {
+                for output_file in output_files {
+                    println!("Would remove: {}", output_file.to_str().unwrap());
                 }
-            });
+                println!("Would forget: {}", source_file.to_str().unwrap());
+            }
+            RunEvent::FilePruned(_, _, output_files, false) => {
+                for output_file in output_files {
+                    println!("Removed: {}", output_file.to_str().unwrap());
+                }
+            }
+            RunEvent::RunSummary(results, failed_profiles) => {
+                let count = |status: ConversionStatus| {
+                    results
+                        .iter()
+                        .filter(|result| result.status == status)
+                        .count()
+                };
+                println!(
+                    "\x1b[1mConverted: {}, Skipped: {}, Failed: {}, Interrupted: {}, Timed out: {}, Failed profiles: {}\x1b[0m",
+                    count(ConversionStatus::Converted),
+                    count(ConversionStatus::Skipped),
+                    count(ConversionStatus::Failed),
+                    count(ConversionStatus::Interrupted),
+                    count(ConversionStatus::TimedOut),
+                    failed_profiles
+                );
+                results
+                    .iter()
+                    .filter(|result| result.status.is_failure())
+                    .for_each(|result| {
+                        eprintln!(
+                            "\x1b[1;31mFailed [{}]: {}\x1b[0m",
+                            result.output_file.to_str().unwrap(),
+                            result.message()
+                        );
+                    });
+            }
+            RunEvent::ProfileFailed(profile, err) => eprintln!(
+                "\x1b[1;31mCould not run '{}' engine: {}\x1b[0m",
+                profile.engine, err
+            ),
+            RunEvent::Watching(profiles) => println!(
+                "\x1b[1mWatching {} for changes (Ctrl+C to stop)\x1b[0m",
+                profiles
+                    .iter()
+                    .map(|profile| profile.source_root.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            RunEvent::Error(err) => eprintln!("\x1b[1;31m{}\x1b[0m", err),
+            _ => (),
+        }
+    }
+
+    fn serialize_event(event: RunEvent) -> serde_json::Value {
+        match event {
+            RunEvent::ProfileStarted(profile) => json!({
+                "event": "profile_started",
+                "profile": profile.id,
+                "engine": profile.engine,
+            }),
+            RunEvent::FilePlanned(profile, source_file, output_file, reason) => json!({
+                "event": "file_planned",
+                "profile": profile.id,
+                "source": source_file,
+                "output_file": output_file,
+                "reason": reason.to_string(),
+            }),
+            RunEvent::FileStarted(profile, source_file, output_file) => json!({
+                "event": "file_started",
+                "profile": profile.id,
+                "source": source_file,
+                "output_file": output_file,
+            }),
+            RunEvent::FileLogged(profile, source_file, message) => json!({
+                "event": "file_logged",
+                "profile": profile.id,
+                "source": source_file,
+                "message": message,
+            }),
+            RunEvent::FileFinished(profile, result) => json!({
+                "event": "file_finished",
+                "profile": profile.id,
+                "source": result.source_file,
+                "output_file": result.output_file,
+                "status": result.status.to_string(),
+                "duration_ms": result.duration.as_millis() as u64,
+                "exit_code": result.exit_code,
+                "output": result.output,
+            }),
+            RunEvent::FilePruned(profile, source_file, output_files, dry_run) => json!({
+                "event": "file_pruned",
+                "profile": profile.id,
+                "source": source_file,
+                "output_files": output_files,
+                "dry_run": dry_run,
+            }),
+            RunEvent::ProfileFinished(profile) => json!({
+                "event": "profile_finished",
+                "profile": profile.id,
+            }),
+            RunEvent::RunSummary(results, failed_profiles) => {
+                let count = |status: ConversionStatus| {
+                    results
+                        .iter()
+                        .filter(|result| result.status == status)
+                        .count()
+                };
+                let failures: Vec<_> = results
+                    .iter()
+                    .filter(|result| result.status.is_failure())
+                    .map(|result| {
+                        json!({
+                            "source": result.source_file,
+                            "output_file": result.output_file,
+                            "status": result.status.to_string(),
+                            "message": result.message(),
+                        })
+                    })
+                    .collect();
+                json!({
+                    "event": "run_summary",
+                    "converted": count(ConversionStatus::Converted),
+                    "skipped": count(ConversionStatus::Skipped),
+                    "failed": count(ConversionStatus::Failed),
+                    "interrupted": count(ConversionStatus::Interrupted),
+                    "timed_out": count(ConversionStatus::TimedOut),
+                    "failed_profiles": failed_profiles,
+                    "failures": failures,
+                })
+            }
+            RunEvent::ProfileFailed(profile, err) => json!({
+                "event": "profile_failed",
+                "profile": profile.id,
+                "engine": profile.engine,
+                "message": err.to_string(),
+            }),
+            RunEvent::Watching(profiles) => json!({
+                "event": "watching",
+                "profiles": profiles.iter().map(|profile| profile.id).collect::<Vec<_>>(),
+            }),
+            RunEvent::Error(err) => json!({
+                "event": "error",
+                "message": err.to_string(),
+            }),
+        }
+    }
+
+    fn unix_time() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64
     }
 
     fn set_spinner_label(&self, label: &str) {
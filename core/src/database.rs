@@ -1,15 +1,14 @@
-use std::{
-    collections::BTreeMap,
-    fs,
-    path::{Path, PathBuf},
-    sync::{Arc, Condvar, Mutex},
-};
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::Arc};
 
 use filetime::FileTime;
 use sqlx::{migrate::MigrateDatabase, Row, Sqlite, SqlitePool};
 use tokio::sync::oneshot::Sender;
 
-use crate::controller::{Controller, DatabaseArgs, ModelEvent, ModelMessageBroker, Profile};
+use crate::controller::{
+    self, Controller, ConversionStatus, DatabaseArgs, FileMetadata, ModelEvent, ModelMessageBroker,
+    Profile, Run, RunRecord, Target,
+};
+use crate::error::TernError;
 
 const DB_URL: &str = "sqlite://.tern/store.db";
 
@@ -28,132 +27,496 @@ impl Database {
         }
     }
 
-    pub async fn connect(&mut self) {
+    pub async fn connect(&mut self) -> Result<(), TernError> {
         let tx = self.tx.take().unwrap();
-        if !Sqlite::database_exists(DB_URL).await.unwrap_or(false) {
-            fs::create_dir(".tern").unwrap();
-            match Sqlite::create_database(DB_URL).await {
-                Err(err) => panic!("Could not create database: {}", err),
-                Ok(_) => Controller::send_write_event(tx).await,
-            };
-        } else if !self.args.as_ref().unwrap().profile_manager {
-            Controller::send_read_event(tx).await;
-        } else {
+        let created = !Sqlite::database_exists(DB_URL).await.unwrap_or(false);
+        if created {
+            fs::create_dir_all(".tern")?;
+            Sqlite::create_database(DB_URL).await?;
+        }
+        self.db = Some(SqlitePool::connect(DB_URL).await?);
+        let args = self.args.as_ref().unwrap();
+        if !args.headless && (created || args.profile_manager) {
             Controller::send_write_event(tx).await;
+        } else {
+            Controller::send_read_event(tx).await;
         }
-        self.db = Some(SqlitePool::connect(DB_URL).await.unwrap());
+        Ok(())
     }
 
-    pub async fn migrate(&self) {
+    pub async fn migrate(&self) -> Result<(), TernError> {
         sqlx::migrate!("./migrations")
             .run(self.db.as_ref().unwrap())
-            .await
-            .unwrap();
+            .await?;
+        Ok(())
     }
 
-    pub async fn get_column(&self, column: Arc<(Mutex<Vec<String>>, Condvar)>, kind: &str) {
-        *column.0.lock().unwrap() = sqlx::query(&format!("SELECT {} FROM profiles", kind))
+    pub async fn get_column(&self, kind: &str) -> Result<Vec<String>, TernError> {
+        sqlx::query(&format!("SELECT {} FROM profiles", kind))
             .fetch_all(self.db.as_ref().unwrap())
-            .await
-            .unwrap()
+            .await?
             .into_iter()
-            .map(|row| row.try_get(kind).unwrap())
-            .collect();
-        column.1.notify_one();
+            .map(|row| Ok(row.try_get(kind)?))
+            .collect()
     }
 
-    pub async fn get_profiles(&self, profile: Arc<(Mutex<Vec<Profile>>, Condvar)>) {
+    pub async fn get_profiles(&self) -> Result<Vec<Profile>, TernError> {
+        // one query per table, grouped by profile here: per-profile queries running at once
+        // would hold a pooled connection each
+        let mut targets = BTreeMap::<u32, Vec<Target>>::new();
+        for row in sqlx::query(
+            "SELECT profile_id, output_file_extension, options FROM targets ORDER BY rowid",
+        )
+        .fetch_all(self.db.as_ref().unwrap())
+        .await?
+        {
+            targets
+                .entry(row.try_get("profile_id")?)
+                .or_default()
+                .push(Target {
+                    output_file_extension: row.try_get("output_file_extension")?,
+                    options: row
+                        .try_get::<Option<String>, &str>("options")?
+                        .filter(|it| !it.is_empty())
+                        .map(|it| it.lines().map(|e| e.into()).collect()),
+                });
+        }
+        let mut metadata = BTreeMap::<u32, BTreeMap<String, BTreeMap<String, FileMetadata>>>::new();
+        for row in sqlx::query(
+            "SELECT profile_id, source_file, target, mtime, hash, fingerprint, output_file FROM metadata",
+        )
+        .fetch_all(self.db.as_ref().unwrap())
+        .await?
+        {
+            metadata
+                .entry(row.try_get("profile_id")?)
+                .or_default()
+                .entry(row.try_get("source_file")?)
+                .or_default()
+                .insert(
+                    row.try_get("target")?,
+                    FileMetadata {
+                        mtime: row.try_get("mtime")?,
+                        hash: row.try_get("hash")?,
+                        fingerprint: row.try_get("fingerprint")?,
+                        output_file: row.try_get("output_file")?,
+                        dependencies: BTreeMap::new(),
+                    },
+                );
+        }
+        for row in sqlx::query(
+            "SELECT profile_id, source_file, target, dependency, mtime FROM dependencies",
+        )
+        .fetch_all(self.db.as_ref().unwrap())
+        .await?
+        {
+            let profile_id: u32 = row.try_get("profile_id")?;
+            let file: String = row.try_get("source_file")?;
+            let target: String = row.try_get("target")?;
+            if let Some(tracked) = metadata
+                .get_mut(&profile_id)
+                .and_then(|files| files.get_mut(&file))
+                .and_then(|targets| targets.get_mut(&target))
+            {
+                tracked
+                    .dependencies
+                    .insert(row.try_get("dependency")?, row.try_get("mtime")?);
+            }
+        }
         let raw_profiles = sqlx::query("SELECT * FROM profiles")
             .fetch_all(self.db.as_ref().unwrap())
-            .await
-            .unwrap();
-        let profiles_future = raw_profiles.into_iter().map(async |row| {
-            let id: u32 = row.try_get("id").unwrap();
-            let try_get_row_as_vector = |column| {
-                // &str: data received from database
-                // String: parse target
-                match row.try_get::<String, &str>(column) {
-                    Ok(it) if !it.is_empty() => Some(it.lines().map(|e| e.into()).collect()),
-                    Ok(_) => None,
-                    Err(err) => panic!("Could not retrieve {}: {}", column, err),
-                }
-            };
-            let options = try_get_row_as_vector("options");
-            let ignore_patterns = try_get_row_as_vector("ignore_patterns");
-            let metadata =
-                sqlx::query("SELECT source_file, mtime FROM metadata WHERE profile_id = $1")
-                    .bind(id)
-                    .fetch_all(self.db.as_ref().unwrap())
-                    .await
-                    .unwrap()
-                    .into_iter()
-                    .fold(Option::<BTreeMap<String, i64>>::None, |acc, row| {
-                        let file = row.try_get("source_file").unwrap();
-                        let mtime = row.try_get("mtime").unwrap();
-                        let mut map = acc.unwrap_or_default();
-                        map.insert(file, mtime);
-                        Some(map)
-                    });
-            Profile {
-                id: row.try_get("id").unwrap(),
-                engine: row.try_get("engine").unwrap(),
-                source_root: row.try_get("source_root").unwrap(),
-                source_file_extension: row.try_get("source_file_extension").unwrap(),
-                output_root: row.try_get("output_root").unwrap(),
-                output_file_extension: row.try_get("output_file_extension").unwrap(),
-                options,
-                ignore_patterns,
-                metadata,
+            .await?;
+        raw_profiles
+            .into_iter()
+            .map(|row| {
+                let id: u32 = row.try_get("id")?;
+                let try_get_row_as_vector = |column| -> Result<Option<Vec<String>>, TernError> {
+                    // &str: data received from database
+                    // String: parse target
+                    Ok(row
+                        .try_get::<Option<String>, &str>(column)?
+                        .filter(|it| !it.is_empty())
+                        .map(|it| it.lines().map(|e| e.into()).collect()))
+                };
+                Ok(Profile {
+                    id: row.try_get("id")?,
+                    engine: row.try_get("engine")?,
+                    source_root: row.try_get("source_root")?,
+                    source_file_extensions: row
+                        .try_get::<String, &str>("source_file_extensions")?
+                        .lines()
+                        .map(Into::into)
+                        .collect(),
+                    case_insensitive: row.try_get("case_insensitive")?,
+                    output_root: row.try_get("output_root")?,
+                    output_file_extension: row.try_get("output_file_extension")?,
+                    output_template: row.try_get("output_template")?,
+                    options: try_get_row_as_vector("options")?,
+                    targets: targets.remove(&id),
+                    ignore_patterns: try_get_row_as_vector("ignore_patterns")?,
+                    tags: try_get_row_as_vector("tags")?,
+                    max_parallel: row.try_get("max_parallel")?,
+                    timeout: row.try_get("timeout")?,
+                    metadata: metadata.remove(&id),
+                })
+            })
+            .collect()
+    }
+
+    pub async fn store_profile(&self, mut profile: Option<Arc<Profile>>) -> Result<(), TernError> {
+        let profile = profile.take().unwrap();
+        let flatten_vector = |v: &Option<Vec<String>>| v.as_ref().map(|option| option.join("\n"));
+        let options = flatten_vector(&profile.options);
+        let ignore_patterns = flatten_vector(&profile.ignore_patterns);
+        let tags = flatten_vector(&profile.tags);
+        let mut transaction = self.db.as_ref().unwrap().begin().await?;
+        let profile_id: u32 = sqlx::query(
+            r#"
+INSERT INTO profiles(engine, source_root, source_file_extensions, case_insensitive, output_root, output_file_extension, output_template, options, ignore_patterns, tags, max_parallel, timeout)
+VALUES
+    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+RETURNING id;
+            "#,
+        )
+        .bind(&profile.engine)
+        .bind(&profile.source_root)
+        .bind(profile.source_file_extensions.join("\n"))
+        .bind(profile.case_insensitive)
+        .bind(&profile.output_root)
+        .bind(&profile.output_file_extension)
+        .bind(&profile.output_template)
+        .bind(options)
+        .bind(ignore_patterns)
+        .bind(tags)
+        .bind(profile.max_parallel)
+        .bind(profile.timeout)
+        .fetch_one(&mut *transaction)
+        .await?
+        .try_get("id")?;
+        Self::store_targets(&mut transaction, profile_id, &profile.targets).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Overwrites every column of the profile sharing `profile.id`; tracked files are kept,
+    /// a changed recipe is picked up by the fingerprint on the next run
+    pub async fn update_profile(&self, mut profile: Option<Arc<Profile>>) -> Result<(), TernError> {
+        let profile = profile.take().unwrap();
+        let flatten_vector = |v: &Option<Vec<String>>| v.as_ref().map(|option| option.join("\n"));
+        let options = flatten_vector(&profile.options);
+        let ignore_patterns = flatten_vector(&profile.ignore_patterns);
+        let tags = flatten_vector(&profile.tags);
+        let mut transaction = self.db.as_ref().unwrap().begin().await?;
+        let stored_extension: String =
+            sqlx::query("SELECT output_file_extension FROM profiles WHERE id = $1")
+                .bind(profile.id)
+                .fetch_one(&mut *transaction)
+                .await?
+                .try_get("output_file_extension")?;
+        sqlx::query(
+            r#"
+UPDATE profiles
+SET engine = $1, source_root = $2, source_file_extensions = $3, case_insensitive = $4,
+    output_root = $5, output_file_extension = $6, output_template = $7, options = $8,
+    ignore_patterns = $9, tags = $10, max_parallel = $11, timeout = $12
+WHERE id = $13
+            "#,
+        )
+        .bind(&profile.engine)
+        .bind(&profile.source_root)
+        .bind(profile.source_file_extensions.join("\n"))
+        .bind(profile.case_insensitive)
+        .bind(&profile.output_root)
+        .bind(&profile.output_file_extension)
+        .bind(&profile.output_template)
+        .bind(options)
+        .bind(ignore_patterns)
+        .bind(tags)
+        .bind(profile.max_parallel)
+        .bind(profile.timeout)
+        .bind(profile.id)
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query("DELETE FROM targets WHERE profile_id = $1")
+            .bind(profile.id)
+            .execute(&mut *transaction)
+            .await?;
+        Self::store_targets(&mut transaction, profile.id as u32, &profile.targets).await?;
+        // files tracked under the former output extension are tracked under the new one, unless
+        // it is still targeted or already tracked (as a former further target)
+        let still_targeted = controller::profile_targets(&profile)
+            .iter()
+            .any(|target| target.output_file_extension == stored_extension);
+        if !still_targeted {
+            for table in ["metadata", "dependencies"] {
+                sqlx::query(&format!(
+                    "UPDATE OR IGNORE {} SET target = $1 WHERE profile_id = $2 AND target = $3",
+                    table
+                ))
+                .bind(&profile.output_file_extension)
+                .bind(profile.id)
+                .bind(&stored_extension)
+                .execute(&mut *transaction)
+                .await?;
+                sqlx::query(&format!(
+                    "DELETE FROM {} WHERE profile_id = $1 AND target = $2",
+                    table
+                ))
+                .bind(profile.id)
+                .bind(&stored_extension)
+                .execute(&mut *transaction)
+                .await?;
             }
-        });
-        *profile.0.lock().unwrap() = futures::future::join_all(profiles_future).await;
-        profile.1.notify_one();
+        }
+        transaction.commit().await?;
+        Ok(())
     }
 
-    pub async fn store_profile(&self, mut profile: Option<Arc<Profile>>) {
-        let profile_arc = profile.take().unwrap();
-        if let Ok(profile) = Arc::try_unwrap(profile_arc) {
-            let flatten_vector = |v: Option<Vec<String>>| v.map(|option| option.join("\n"));
-            let options = flatten_vector(profile.options);
-            let ignore_patterns = flatten_vector(profile.ignore_patterns);
+    async fn store_targets(
+        transaction: &mut sqlx::Transaction<'_, Sqlite>,
+        profile_id: u32,
+        targets: &Option<Vec<Target>>,
+    ) -> Result<(), TernError> {
+        for target in targets.iter().flatten() {
             sqlx::query(
-                r#"
-INSERT INTO profiles(engine, source_root, source_file_extension, output_root, output_file_extension, options, ignore_patterns)
-VALUES
-    ($1, $2, $3, $4, $5, $6, $7)
-                "#,
+                "INSERT INTO targets (profile_id, output_file_extension, options) VALUES ($1, $2, $3)",
             )
-            .bind(profile.engine)
-            .bind(profile.source_root)
-            .bind(profile.source_file_extension)
-            .bind(profile.output_root)
-            .bind(profile.output_file_extension)
-            .bind(options)
-            .bind(ignore_patterns)
-            .execute(self.db.as_ref().unwrap())
-            .await
-            .unwrap();
+            .bind(profile_id)
+            .bind(&target.output_file_extension)
+            .bind(target.options.as_ref().map(|options| options.join("\n")))
+            .execute(&mut **transaction)
+            .await?;
         }
+        Ok(())
     }
 
-    /// 1. Inserts a new row if the source_file doesn't exist for this profile
-    /// 2. Updates the mtime if the source_file already exists
-    pub async fn update_metadata(&self, source_file: PathBuf, profile_id: u8) {
-        let mtime = FileTime::from_last_modification_time(&fs::metadata(&source_file).unwrap())
-            .unix_seconds();
+    /// Removes a profile; its metadata, dependencies and run results follow through
+    /// `ON DELETE CASCADE` (sqlx enables foreign keys on every SQLite connection)
+    pub async fn delete_profile(&self, profile_id: u8) -> Result<(), TernError> {
+        sqlx::query("DELETE FROM profiles WHERE id = $1")
+            .bind(profile_id)
+            .execute(self.db.as_ref().unwrap())
+            .await?;
+        Ok(())
+    }
+
+    /// 1. Inserts a new row if the source_file doesn't exist for this profile target
+    /// 2. Updates the mtime (and content hash) if the source_file already exists
+    ///
+    /// The fingerprint records the converter script, options and output extension used;
+    /// dependencies reported by the converter replace the previously tracked ones
+    pub async fn update_metadata(
+        &self,
+        source_file: PathBuf,
+        profile_id: u8,
+        target: String,
+        output_file: PathBuf,
+        fingerprint: String,
+        dependencies: Vec<PathBuf>,
+    ) -> Result<(), TernError> {
+        let mtime =
+            FileTime::from_last_modification_time(&fs::metadata(&source_file)?).unix_seconds();
+        let hash = if self.args.as_ref().unwrap().content_hash {
+            controller::hash_file(&source_file)
+        } else {
+            None
+        };
         sqlx::query(
             r#"
-INSERT INTO metadata (profile_id, source_file, mtime)
-VALUES ($1, $2, $3)
-ON CONFLICT(profile_id, source_file) 
-DO UPDATE SET mtime = $3;
+INSERT INTO metadata (profile_id, source_file, target, mtime, hash, fingerprint, output_file)
+VALUES ($1, $2, $3, $4, $5, $6, $7)
+ON CONFLICT(profile_id, source_file, target)
+DO UPDATE SET mtime = $4, hash = $5, fingerprint = $6, output_file = $7;
         "#,
         )
         .bind(profile_id)
         .bind(source_file.to_str().unwrap())
+        .bind(&target)
         .bind(mtime)
+        .bind(hash)
+        .bind(fingerprint)
+        .bind(output_file.to_str().unwrap())
+        .execute(self.db.as_ref().unwrap())
+        .await?;
+        sqlx::query(
+            "DELETE FROM dependencies WHERE profile_id = $1 AND source_file = $2 AND target = $3",
+        )
+        .bind(profile_id)
+        .bind(source_file.to_str().unwrap())
+        .bind(&target)
         .execute(self.db.as_ref().unwrap())
-        .await
-        .unwrap();
+        .await?;
+        for dependency in dependencies {
+            // dependencies that do not exist cannot be tracked
+            let Ok(dependency_metadata) = fs::metadata(&dependency) else {
+                continue;
+            };
+            sqlx::query(
+                r#"
+INSERT INTO dependencies (profile_id, source_file, target, dependency, mtime)
+VALUES ($1, $2, $3, $4, $5)
+ON CONFLICT(profile_id, source_file, target, dependency)
+DO UPDATE SET mtime = $5;
+            "#,
+            )
+            .bind(profile_id)
+            .bind(source_file.to_str().unwrap())
+            .bind(&target)
+            .bind(dependency.to_str().unwrap())
+            .bind(FileTime::from_last_modification_time(&dependency_metadata).unix_seconds())
+            .execute(self.db.as_ref().unwrap())
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Records the current mtime of a source file whose content did not change, so it is not
+    /// hashed again on every run
+    pub async fn refresh_mtime(
+        &self,
+        source_file: PathBuf,
+        profile_id: u8,
+        target: String,
+    ) -> Result<(), TernError> {
+        let mtime =
+            FileTime::from_last_modification_time(&fs::metadata(&source_file)?).unix_seconds();
+        sqlx::query(
+            "UPDATE metadata SET mtime = $1 WHERE profile_id = $2 AND source_file = $3 AND target = $4",
+        )
+        .bind(mtime)
+        .bind(profile_id)
+        .bind(source_file.to_str().unwrap())
+        .bind(target)
+        .execute(self.db.as_ref().unwrap())
+        .await?;
+        Ok(())
+    }
+
+    /// Forgets a source file, along with its tracked targets and dependencies
+    pub async fn remove_metadata(
+        &self,
+        source_file: PathBuf,
+        profile_id: u8,
+    ) -> Result<(), TernError> {
+        for table in ["metadata", "dependencies"] {
+            sqlx::query(&format!(
+                "DELETE FROM {} WHERE profile_id = $1 AND source_file = $2",
+                table
+            ))
+            .bind(profile_id)
+            .bind(source_file.to_str().unwrap())
+            .execute(self.db.as_ref().unwrap())
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Stores a run along with the outcome of every file it did not skip
+    pub async fn store_run(&self, run: Run) -> Result<(), TernError> {
+        let count = |status: ConversionStatus| {
+            run.results
+                .iter()
+                .filter(|result| result.status == status)
+                .count() as u32
+        };
+        let mut transaction = self.db.as_ref().unwrap().begin().await?;
+        let run_id: u32 = sqlx::query(
+            r#"
+INSERT INTO runs (started_at, finished_at, arguments, converted, skipped, failed, interrupted, timed_out, failed_profiles)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+RETURNING id;
+        "#,
+        )
+        .bind(run.started_at)
+        .bind(run.finished_at)
+        .bind(&run.arguments)
+        .bind(count(ConversionStatus::Converted))
+        .bind(count(ConversionStatus::Skipped))
+        .bind(count(ConversionStatus::Failed))
+        .bind(count(ConversionStatus::Interrupted))
+        .bind(count(ConversionStatus::TimedOut))
+        .bind(run.failed_profiles)
+        .fetch_one(&mut *transaction)
+        .await?
+        .try_get("id")?;
+        for result in run
+            .results
+            .iter()
+            .filter(|result| result.status != ConversionStatus::Skipped)
+        {
+            sqlx::query(
+                r#"
+INSERT INTO run_results (run_id, profile_id, source_file, output_file, status, duration_ms, exit_code, message)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+            )
+            .bind(run_id)
+            .bind(result.profile_id)
+            .bind(result.source_file.to_str().unwrap())
+            .bind(result.output_file.to_str().unwrap())
+            .bind(result.status.to_string())
+            .bind(result.duration.as_millis() as i64)
+            .bind(result.exit_code)
+            // failures are described even without output, conversions keep what they printed
+            .bind(if result.status.is_failure() {
+                Some(result.message())
+            } else {
+                result.output.clone()
+            })
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_runs(&self, limit: u32) -> Result<Vec<RunRecord>, TernError> {
+        sqlx::query(
+            r#"
+SELECT id, datetime(started_at, 'unixepoch', 'localtime') AS started_at,
+    datetime(finished_at, 'unixepoch', 'localtime') AS finished_at,
+    arguments, converted, skipped, failed, interrupted, timed_out, failed_profiles
+FROM runs
+ORDER BY id DESC
+LIMIT $1
+        "#,
+        )
+        .bind(limit)
+        .fetch_all(self.db.as_ref().unwrap())
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok(RunRecord {
+                id: row.try_get("id")?,
+                started_at: row.try_get("started_at")?,
+                finished_at: row.try_get("finished_at")?,
+                arguments: row.try_get("arguments")?,
+                converted: row.try_get("converted")?,
+                skipped: row.try_get("skipped")?,
+                failed: row.try_get("failed")?,
+                interrupted: row.try_get("interrupted")?,
+                timed_out: row.try_get("timed_out")?,
+                failed_profiles: row.try_get("failed_profiles")?,
+            })
+        })
+        .collect()
+    }
+
+    /// Returns (profile_id, source_file) of the files that failed (or timed out) in the latest
+    /// run; every target of such a file is retried
+    pub async fn get_failed_files(&self) -> Result<Vec<(u8, String)>, TernError> {
+        sqlx::query(
+            r#"
+SELECT DISTINCT profile_id, source_file FROM run_results
+WHERE status IN ($1, $2) AND run_id = (SELECT MAX(id) FROM runs)
+        "#,
+        )
+        .bind(ConversionStatus::Failed.to_string())
+        .bind(ConversionStatus::TimedOut.to_string())
+        .fetch_all(self.db.as_ref().unwrap())
+        .await?
+        .into_iter()
+        .map(|row| Ok((row.try_get("profile_id")?, row.try_get("source_file")?)))
+        .collect()
     }
 }
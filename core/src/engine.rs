@@ -0,0 +1,270 @@
+use flate2::{write::GzEncoder, Compression};
+use mlua::{Function, Lua, MultiValue, Value};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+    sync::{atomic::AtomicBool, Arc, LazyLock, OnceLock, RwLock},
+    time::Instant,
+};
+
+use crate::controller::{self, Profile};
+use crate::error::TernError;
+use crate::host;
+
+/// Converters registered by engine name, checked before the scripts of the converters directory
+static CONVERTERS: LazyLock<RwLock<BTreeMap<String, Arc<dyn Converter>>>> = LazyLock::new(|| {
+    let mut converters: BTreeMap<String, Arc<dyn Converter>> = BTreeMap::new();
+    converters.insert("gzip".to_string(), Arc::new(Gzip));
+    converters.insert("json-to-yaml".to_string(), Arc::new(JsonToYaml));
+    RwLock::new(converters)
+});
+
+/// A source file to convert to one target of its profile
+pub struct Job<'a> {
+    pub profile: &'a Profile,
+    pub source_file: &'a Path,
+    pub output_file: &'a Path,
+    pub options: &'a [String],
+    /// When the profile's timeout runs out; processes still running by then should be killed,
+    /// and the conversion reported as timed out
+    pub deadline: Option<Instant>,
+    /// Set once the run is cancelled; long conversions should stop early
+    pub interrupt: &'a Arc<AtomicBool>,
+}
+
+/// Converts one file at a time; a converter is shared by every thread of a run
+pub trait Converter: Send + Sync {
+    fn convert(&self, job: &Job) -> ConverterReturn;
+}
+
+/// Outcome of a conversion
+#[derive(Debug, Default)]
+pub struct ConverterReturn {
+    pub success: bool,
+    pub exit_code: Option<i64>,
+    pub output: Option<String>,
+    /// Paths the source file depends on, relative to it
+    pub dependencies: Vec<String>,
+    /// Messages reported along with the file
+    pub messages: Vec<String>,
+    /// Whether the converter was stopped at the job's deadline; conversions that complete late
+    /// keep their output
+    pub timed_out: bool,
+}
+
+impl ConverterReturn {
+    pub fn succeeded() -> Self {
+        Self {
+            success: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn failed(message: String) -> Self {
+        Self {
+            output: Some(message),
+            ..Default::default()
+        }
+    }
+}
+
+impl<E: Error> From<Result<(), E>> for ConverterReturn {
+    fn from(result: Result<(), E>) -> Self {
+        match result {
+            Ok(()) => Self::succeeded(),
+            Err(err) => Self::failed(err.to_string()),
+        }
+    }
+}
+
+/// Registers a converter under an engine name, taking precedence over a script of that name
+pub fn register_converter(engine: impl Into<String>, converter: impl Converter + 'static) {
+    CONVERTERS
+        .write()
+        .unwrap()
+        .insert(engine.into(), Arc::new(converter));
+}
+
+/// Names of the registered converters and of the scripts in the converters directory
+pub fn available_engines() -> Vec<String> {
+    let mut engines: Vec<String> = CONVERTERS.read().unwrap().keys().cloned().collect();
+    engines.extend(
+        controller::read_data_dir()
+            .filter_map(|entry| entry.ok().and_then(|e| e.file_name().into_string().ok())),
+    );
+    engines.sort();
+    engines.dedup();
+    engines
+}
+
+pub fn engine_exists(engine: &str) -> bool {
+    CONVERTERS.read().unwrap().contains_key(engine)
+        || controller::get_converters_dir().join(engine).is_file()
+}
+
+/// Resolves the converter of a profile: a registered one, otherwise its Lua script
+pub fn load_converter(profile: &Profile) -> Result<Arc<dyn Converter>, TernError> {
+    if let Some(converter) = CONVERTERS.read().unwrap().get(&profile.engine) {
+        return Ok(Arc::clone(converter));
+    }
+    if !controller::get_converters_dir()
+        .join(&profile.engine)
+        .is_file()
+    {
+        return Err(TernError::EngineNotFound(profile.engine.clone()));
+    }
+    Ok(Arc::new(LuaConverter::load(profile)?))
+}
+
+/// A Lua script returning a function called with the source file, the output file and the
+/// options of the target
+struct LuaConverter {
+    // one state per rayon worker, loaded on its first file, so scripts run in parallel
+    // and never share globals
+    states: Vec<OnceLock<Result<LuaState, String>>>,
+    // serves threads outside of the rayon pool; loaded upfront so a broken script fails early
+    fallback: LuaState,
+}
+
+/// A Lua state along with the function its script returned
+struct LuaState {
+    lua: Lua,
+    function: Function,
+}
+
+impl LuaState {
+    // the script runs along with the `tern` table of its profile
+    fn load(profile: &Profile) -> Result<Self, TernError> {
+        let lua = host::lua(profile)?;
+        let function = lua
+            .load(controller::get_converters_dir().join(&profile.engine))
+            .eval()?;
+        Ok(Self { lua, function })
+    }
+}
+
+impl LuaConverter {
+    fn load(profile: &Profile) -> Result<Self, TernError> {
+        Ok(Self {
+            states: (0..rayon::current_num_threads())
+                .map(|_| OnceLock::new())
+                .collect(),
+            fallback: LuaState::load(profile)?,
+        })
+    }
+
+    // state of the calling thread
+    fn state(&self, profile: &Profile) -> Result<&LuaState, String> {
+        match rayon::current_thread_index().and_then(|index| self.states.get(index)) {
+            Some(state) => state
+                .get_or_init(|| LuaState::load(profile).map_err(|err| err.to_string()))
+                .as_ref()
+                .map_err(Clone::clone),
+            None => Ok(&self.fallback),
+        }
+    }
+
+    /// Values returned by the script: either a status followed by what `os.execute` returns
+    /// (`kind, code`), or a table { success = bool, exit_code = int, output = string,
+    /// dependencies = { path... } }
+    fn parse(values: MultiValue) -> ConverterReturn {
+        let mut values = values.into_iter();
+        match values.next() {
+            Some(Value::Table(table)) => ConverterReturn {
+                success: table.get::<bool>("success").unwrap_or(false),
+                exit_code: table.get::<Option<i64>>("exit_code").ok().flatten(),
+                output: table.get::<Option<String>>("output").ok().flatten(),
+                dependencies: table
+                    .get::<Option<Vec<String>>>("dependencies")
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default(),
+                ..Default::default()
+            },
+            value => ConverterReturn {
+                success: !matches!(value, None | Some(Value::Nil | Value::Boolean(false))),
+                exit_code: match values.nth(1) {
+                    Some(Value::Integer(code)) => Some(code),
+                    _ => None,
+                },
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Converter for LuaConverter {
+    fn convert(&self, job: &Job) -> ConverterReturn {
+        let state = match self.state(job.profile) {
+            Ok(state) => state,
+            Err(err) => return ConverterReturn::failed(err),
+        };
+        host::set_limits(&state.lua, job.deadline, job.interrupt);
+        // scripts have always been handed `{ "" }` for targets without options
+        let options = match job.options {
+            [] => vec![String::new()],
+            options => options.to_vec(),
+        };
+        let mut returned = state
+            .function
+            .call::<MultiValue>((
+                job.source_file.to_str().unwrap(),
+                job.output_file.to_str().unwrap(),
+                options,
+            ))
+            .map_or_else(|err| ConverterReturn::failed(err.to_string()), Self::parse);
+        // what the programs printed is kept even when the script does not return it
+        let captured = host::take_output(&state.lua);
+        if returned.output.is_none() {
+            returned.output = captured;
+        }
+        returned.messages = host::take_logs(&state.lua);
+        returned.timed_out = host::timed_out(&state.lua);
+        returned
+    }
+}
+
+/// Compresses a file with gzip; an option from `-1` to `-9` sets the compression level
+struct Gzip;
+
+impl Converter for Gzip {
+    fn convert(&self, job: &Job) -> ConverterReturn {
+        let level = job
+            .options
+            .iter()
+            .filter_map(|option| option.strip_prefix('-')?.parse().ok())
+            .rfind(|level| (1..=9).contains(level))
+            .unwrap_or(6);
+        let result: io::Result<()> = try {
+            let mut encoder =
+                GzEncoder::new(File::create(job.output_file)?, Compression::new(level));
+            io::copy(&mut File::open(job.source_file)?, &mut encoder)?;
+            encoder.finish()?;
+        };
+        result.into()
+    }
+}
+
+/// Rewrites a JSON document as YAML
+struct JsonToYaml;
+
+impl JsonToYaml {
+    fn rewrite(source_file: &Path, output_file: &Path) -> Result<(), Box<dyn Error>> {
+        let source = BufReader::new(File::open(source_file)?);
+        let value: serde_json::Value = serde_json::from_reader(source)?;
+        serde_yaml::to_writer(File::create(output_file)?, &value)?;
+        Ok(())
+    }
+}
+
+impl Converter for JsonToYaml {
+    fn convert(&self, job: &Job) -> ConverterReturn {
+        match Self::rewrite(job.source_file, job.output_file) {
+            Ok(()) => ConverterReturn::succeeded(),
+            Err(err) => ConverterReturn::failed(err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::{fmt, io, path::PathBuf};
+
+#[derive(Debug)]
+pub enum TernError {
+    Io(io::Error),
+    Database(sqlx::Error),
+    Migration(sqlx::migrate::MigrateError),
+    Lua(mlua::Error),
+    Watch(notify_debouncer_mini::notify::Error),
+    Pattern(globset::Error),
+    IgnorePattern(ignore::Error),
+    ProfileNotFound(u8),
+    EngineNotFound(String),
+    NoMatchingProfile,
+    InvalidTarget(String),
+    DuplicateTarget(String),
+    InvalidTemplate(String),
+    TemplateOutsideOutputRoot(String),
+    InvalidLimit(&'static str, String),
+    OutputCollision(PathBuf, PathBuf, PathBuf),
+    OutsideSourceRoot(PathBuf, String),
+}
+
+impl fmt::Display for TernError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TernError::Io(err) => write!(f, "I/O error: {}", err),
+            TernError::Database(err) => write!(f, "Database error: {}", err),
+            TernError::Migration(err) => write!(f, "Could not migrate database: {}", err),
+            TernError::Lua(err) => write!(f, "Lua error: {}", err),
+            TernError::Watch(err) => write!(f, "Could not watch for changes: {}", err),
+            TernError::Pattern(err) => write!(f, "Invalid source file pattern: {}", err),
+            TernError::IgnorePattern(err) => write!(f, "Invalid ignore pattern: {}", err),
+            TernError::ProfileNotFound(id) => write!(f, "No profile with id {}", id),
+            TernError::EngineNotFound(engine) => write!(
+                f,
+                "Engine '{}' is neither a registered converter nor a script in {}",
+                engine,
+                crate::controller::get_converters_dir().display()
+            ),
+            TernError::NoMatchingProfile => write!(f, "No profile matches the selection"),
+            TernError::InvalidTarget(target) => write!(
+                f,
+                "Invalid output target '{}', expected an extension followed by options",
+                target
+            ),
+            TernError::DuplicateTarget(extension) => {
+                write!(
+                    f,
+                    "Output extension '{}' is targeted more than once",
+                    extension
+                )
+            }
+            TernError::InvalidTemplate(template) => write!(
+                f,
+                "Invalid output template '{}', placeholders are {{{}}}",
+                template,
+                crate::controller::TEMPLATE_PLACEHOLDERS.join("}, {")
+            ),
+            TernError::TemplateOutsideOutputRoot(template) => write!(
+                f,
+                "Output template '{}' must start with {{output_root}}/ and stay under it",
+                template
+            ),
+            TernError::InvalidLimit(limit, value) => {
+                write!(f, "Invalid {} '{}', expected a number", limit, value)
+            }
+            TernError::OutputCollision(output_file, source_file, other_source_file) => write!(
+                f,
+                "{} and {} would both be converted to {}",
+                source_file.display(),
+                other_source_file.display(),
+                output_file.display()
+            ),
+            TernError::OutsideSourceRoot(source_file, source_root) => write!(
+                f,
+                "{} is not under the source root {}",
+                source_file.display(),
+                source_root
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TernError {}
+
+impl From<io::Error> for TernError {
+    fn from(err: io::Error) -> Self {
+        TernError::Io(err)
+    }
+}
+
+impl From<sqlx::Error> for TernError {
+    fn from(err: sqlx::Error) -> Self {
+        TernError::Database(err)
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for TernError {
+    fn from(err: sqlx::migrate::MigrateError) -> Self {
+        TernError::Migration(err)
+    }
+}
+
+impl From<mlua::Error> for TernError {
+    fn from(err: mlua::Error) -> Self {
+        TernError::Lua(err)
+    }
+}
+
+impl From<notify_debouncer_mini::notify::Error> for TernError {
+    fn from(err: notify_debouncer_mini::notify::Error) -> Self {
+        TernError::Watch(err)
+    }
+}
+
+impl From<globset::Error> for TernError {
+    fn from(err: globset::Error) -> Self {
+        TernError::Pattern(err)
+    }
+}
+
+impl From<ignore::Error> for TernError {
+    fn from(err: ignore::Error) -> Self {
+        TernError::IgnorePattern(err)
+    }
+}
@@ -0,0 +1,557 @@
+use mlua::{HookTriggers, Lua, Table, VmState};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Read,
+    path::{Component, Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tempfile::TempDir;
+
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+
+use crate::controller::Profile;
+use crate::error::TernError;
+
+/// Messages converters passed to `tern.log`, collected until the converter takes them
+#[derive(Default)]
+struct Logs(Vec<String>);
+
+/// Output of the programs run through `tern.exec`, recorded with the file being converted
+/// whether or not the converter returns it
+#[derive(Default)]
+struct Captured(String);
+
+/// Directories handed out by `tern.tempdir`, removed along with the Lua state
+#[derive(Default)]
+struct TempDirs(Vec<TempDir>);
+
+/// Programs started through `tern.exec` that are still running, by process id
+static RUNNING: LazyLock<Mutex<BTreeSet<u32>>> = LazyLock::new(Default::default);
+
+/// Bounds of the file being converted: when it runs out of time, and whether the run is
+/// cancelled; either one stops the programs started through `tern.exec`
+#[derive(Default, Clone)]
+struct Limits {
+    deadline: Option<Instant>,
+    interrupt: Option<Arc<AtomicBool>>,
+    // set once the file was stopped for running out of time
+    timed_out: bool,
+}
+
+impl Limits {
+    fn expired(&self, now: Instant) -> bool {
+        self.deadline.is_some_and(|deadline| now >= deadline)
+    }
+
+    fn interrupted(&self) -> bool {
+        self.interrupt
+            .as_ref()
+            .is_some_and(|interrupt| interrupt.load(Ordering::SeqCst))
+    }
+}
+
+/// A program registered as running until dropped
+struct Running(u32);
+
+impl Running {
+    fn new(pid: u32) -> Self {
+        RUNNING.lock().unwrap().insert(pid);
+        Self(pid)
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Options of `tern.exec`
+#[derive(Default)]
+struct ExecOptions {
+    env: HashMap<String, String>,
+    cwd: Option<PathBuf>,
+    timeout: Option<Duration>,
+}
+
+/// Creates a Lua state exposing the `tern` table to the converter of a profile
+pub fn lua(profile: &Profile) -> Result<Lua, TernError> {
+    let lua = Lua::new();
+    register(&lua, profile)?;
+    Ok(lua)
+}
+
+/// Registers the `tern` table:
+/// - `tern.exec({ program, args... }, { env = {}, cwd = path, timeout = seconds })` runs a
+///   program without a shell and returns what a converter returns:
+///   { success = bool, exit_code = int, output = string, stdout, stderr, timed_out = bool };
+///   the program is killed along with its process group once the file runs out of time or
+///   the run is cancelled; its output is also kept for the run history
+/// - `tern.shell_quote(string)` quotes a string for scripts that still go through a shell
+/// - `tern.log(message)` reports a message along with the file being converted
+/// - `tern.tempdir()` creates a directory removed once the profile has run
+/// - `tern.which(program)` finds a program in `PATH`
+/// - `tern.path.{name, stem, ext, parent, join, relative}` take paths apart and together
+/// - `tern.file_exists(path)` tells whether a file (or directory) exists
+/// - `tern.profile` describes the profile being run
+///
+/// `os.execute` runs its command like `tern.exec` does, and scripts that compute past their time
+/// are stopped as well; `io.popen` is left as Lua has it, so the programs it starts are neither
+/// killed nor bounded by the timeout
+pub fn register(lua: &Lua, profile: &Profile) -> mlua::Result<()> {
+    lua.set_app_data(Logs::default());
+    lua.set_app_data(Captured::default());
+    lua.set_app_data(TempDirs::default());
+    lua.set_app_data(Limits::default());
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(10_000),
+        |lua, _| check_limits(lua),
+    );
+    let os: Table = lua.globals().get("os")?;
+    os.set("execute", lua.create_function(execute)?)?;
+    let tern = lua.create_table()?;
+    tern.set(
+        "exec",
+        lua.create_function(|lua, (argv, options): (Vec<String>, Option<Table>)| {
+            exec(
+                lua,
+                argv,
+                options.map(exec_options).transpose()?.unwrap_or_default(),
+            )
+        })?,
+    )?;
+    tern.set(
+        "shell_quote",
+        lua.create_function(|_, value: String| Ok(shell_quote(&value)))?,
+    )?;
+    tern.set(
+        "log",
+        lua.create_function(|lua, message: String| {
+            if let Some(mut logs) = lua.app_data_mut::<Logs>() {
+                logs.0.push(message);
+            }
+            Ok(())
+        })?,
+    )?;
+    tern.set(
+        "tempdir",
+        lua.create_function(|lua, ()| {
+            let tempdir = tempfile::Builder::new().prefix("tern-").tempdir()?;
+            let path = tempdir.path().to_string_lossy().into_owned();
+            if let Some(mut tempdirs) = lua.app_data_mut::<TempDirs>() {
+                tempdirs.0.push(tempdir);
+            }
+            Ok(path)
+        })?,
+    )?;
+    tern.set(
+        "which",
+        lua.create_function(|_, program: String| {
+            Ok(which::which(program)
+                .ok()
+                .map(|path| path.to_string_lossy().into_owned()))
+        })?,
+    )?;
+    tern.set(
+        "file_exists",
+        lua.create_function(|_, path: String| Ok(Path::new(&path).exists()))?,
+    )?;
+    tern.set("path", path_table(lua)?)?;
+    tern.set("profile", profile_table(lua, profile)?)?;
+    lua.globals().set("tern", tern)
+}
+
+/// Bounds the script, and the programs it runs, until the next call
+pub fn set_limits(lua: &Lua, deadline: Option<Instant>, interrupt: &Arc<AtomicBool>) {
+    lua.set_app_data(Limits {
+        deadline,
+        interrupt: Some(Arc::clone(interrupt)),
+        timed_out: false,
+    });
+}
+
+/// Tells whether the file was stopped for running out of time since the limits were set
+pub fn timed_out(lua: &Lua) -> bool {
+    lua.app_data_ref::<Limits>()
+        .is_some_and(|limits| limits.timed_out)
+}
+
+/// Kills the programs still running through `tern.exec`, along with whatever they started
+pub fn kill_running() {
+    #[cfg(unix)]
+    for pid in RUNNING.lock().unwrap().iter() {
+        unsafe { libc::kill(-(*pid as libc::pid_t), libc::SIGKILL) };
+    }
+}
+
+/// Takes the output of the programs run since the last call, if they printed anything
+pub fn take_output(lua: &Lua) -> Option<String> {
+    lua.app_data_mut::<Captured>()
+        .map(|mut captured| std::mem::take(&mut captured.0))
+        .filter(|output| !output.trim().is_empty())
+}
+
+/// Takes the messages logged since the last call
+pub fn take_logs(lua: &Lua) -> Vec<String> {
+    lua.app_data_mut::<Logs>()
+        .map(|mut logs| std::mem::take(&mut logs.0))
+        .unwrap_or_default()
+}
+
+fn exec_options(table: Table) -> mlua::Result<ExecOptions> {
+    Ok(ExecOptions {
+        env: table
+            .get::<Option<HashMap<String, String>>>("env")?
+            .unwrap_or_default(),
+        cwd: table.get::<Option<String>>("cwd")?.map(PathBuf::from),
+        timeout: table
+            .get::<Option<f64>>("timeout")?
+            .map(Duration::from_secs_f64),
+    })
+}
+
+fn exec(lua: &Lua, argv: Vec<String>, options: ExecOptions) -> mlua::Result<Table> {
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| mlua::Error::runtime("tern.exec expects at least a program"))?;
+    let mut command = Command::new(program);
+    command.args(args).envs(&options.env);
+    if let Some(cwd) = &options.cwd {
+        command.current_dir(cwd);
+    }
+    let returned = lua.create_table()?;
+    let outcome = match run(lua, command, options.timeout) {
+        Ok(outcome) => outcome,
+        Err(err) => {
+            let output = format!("Could not run {}: {}", program, err);
+            capture(lua, &format!("{}\n", output));
+            returned.set("success", false)?;
+            returned.set("timed_out", false)?;
+            returned.set("output", output)?;
+            return Ok(returned);
+        }
+    };
+    returned.set(
+        "success",
+        outcome.status.is_some_and(|status| status.success()),
+    )?;
+    returned.set("exit_code", outcome.status.and_then(|status| status.code()))?;
+    returned.set("timed_out", outcome.timed_out)?;
+    returned.set("output", format!("{}{}", outcome.stdout, outcome.stderr))?;
+    returned.set("stdout", outcome.stdout)?;
+    returned.set("stderr", outcome.stderr)?;
+    Ok(returned)
+}
+
+// `os.execute` of Lua 5.4, run like `tern.exec` so the profile's timeout and cancellation
+// apply to it too: `true` or nil, then "exit" and the exit code, or "signal" and the signal
+fn execute(
+    lua: &Lua,
+    command: Option<String>,
+) -> mlua::Result<(Option<bool>, Option<&'static str>, Option<i32>)> {
+    let Some(command) = command else {
+        return Ok((Some(true), None, None));
+    };
+    #[cfg(unix)]
+    let mut shell = Command::new("/bin/sh");
+    #[cfg(unix)]
+    shell.arg("-c");
+    #[cfg(windows)]
+    let mut shell = Command::new("cmd");
+    #[cfg(windows)]
+    shell.arg("/C");
+    shell.arg(command);
+    let status = match run(lua, shell, None) {
+        Ok(outcome) => outcome.status,
+        Err(err) => {
+            capture(lua, &format!("Could not run the shell: {}\n", err));
+            return Ok((None, Some("exit"), Some(127)));
+        }
+    };
+    Ok(
+        match status.map(|status| (status.success(), status.code())) {
+            Some((success, Some(code))) => (success.then_some(true), Some("exit"), Some(code)),
+            // killed, by tern or otherwise
+            _ => (None, Some("signal"), status.and_then(signal).or(Some(9))),
+        },
+    )
+}
+
+#[cfg(unix)]
+fn signal(status: ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_: ExitStatus) -> Option<i32> {
+    None
+}
+
+/// What a program run through `tern.exec` or `os.execute` came to
+struct Outcome {
+    // None once killed
+    status: Option<ExitStatus>,
+    timed_out: bool,
+    stdout: String,
+    stderr: String,
+}
+
+// Runs a program in a process group of its own, killed along with the group once `timeout`
+// (or the time left to the file) runs out or the run is cancelled; its output is captured
+fn run(lua: &Lua, mut command: Command, timeout: Option<Duration>) -> std::io::Result<Outcome> {
+    command
+        // converters run unattended, nothing may wait on stdin
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // a group of its own, so that whatever the program starts is killed along with it
+    #[cfg(unix)]
+    command.process_group(0);
+    let mut child = command.spawn()?;
+    // pipes are drained while waiting, a full pipe would block the child
+    let read = |mut pipe: Box<dyn Read + Send>| {
+        thread::spawn(move || {
+            let mut buffer = Vec::new();
+            let _ = pipe.read_to_end(&mut buffer);
+            String::from_utf8_lossy(&buffer).into_owned()
+        })
+    };
+    let stdout = read(Box::new(child.stdout.take().unwrap()));
+    let stderr = read(Box::new(child.stderr.take().unwrap()));
+    let running = Running::new(child.id());
+    let limits = lua
+        .app_data_ref::<Limits>()
+        .map(|limits| limits.clone())
+        .unwrap_or_default();
+    let deadline = timeout
+        .map(|timeout| Instant::now() + timeout)
+        .into_iter()
+        .chain(limits.deadline)
+        .min();
+    let mut timed_out = false;
+    // a cancelled run does not wait for its programs either
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        let now = Instant::now();
+        timed_out = deadline.is_some_and(|deadline| now >= deadline);
+        if timed_out || limits.interrupted() {
+            kill(&mut child)?;
+            child.wait()?;
+            // the file ran out of time, not only this program
+            if limits.expired(now) {
+                set_timed_out(lua);
+            }
+            break None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    drop(running);
+    let outcome = Outcome {
+        status,
+        timed_out,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    };
+    capture(lua, &format!("{}{}", outcome.stdout, outcome.stderr));
+    Ok(outcome)
+}
+
+// Stops scripts that run out of time or get cancelled while computing rather than waiting on
+// a program; errors raised here end the conversion
+fn check_limits(lua: &Lua) -> mlua::Result<VmState> {
+    let (expired, interrupted) = lua
+        .app_data_ref::<Limits>()
+        .map(|limits| (limits.expired(Instant::now()), limits.interrupted()))
+        .unwrap_or_default();
+    if expired {
+        set_timed_out(lua);
+        return Err(mlua::Error::runtime("the file ran out of time"));
+    }
+    if interrupted {
+        return Err(mlua::Error::runtime("the run was cancelled"));
+    }
+    Ok(VmState::Continue)
+}
+
+fn set_timed_out(lua: &Lua) {
+    if let Some(mut limits) = lua.app_data_mut::<Limits>() {
+        limits.timed_out = true;
+    }
+}
+
+fn capture(lua: &Lua, output: &str) {
+    if let Some(mut captured) = lua.app_data_mut::<Captured>() {
+        captured.0.push_str(output);
+    }
+}
+
+// Kills a child along with its process group
+fn kill(child: &mut Child) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } == 0 {
+        return Ok(());
+    }
+    child.kill()
+}
+
+/// Whether a process is still running; without a way to tell, it is assumed to be
+pub fn process_alive(pid: u32) -> bool {
+    // signal 0 only checks that the process exists; EPERM means it belongs to someone else
+    #[cfg(unix)]
+    return unsafe { libc::kill(pid as libc::pid_t, 0) } == 0
+        || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        true
+    }
+}
+
+// Single-quotes a string for POSIX shells, closing the quote around embedded quotes
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn path_table(lua: &Lua) -> mlua::Result<Table> {
+    let path = lua.create_table()?;
+    let lossy =
+        |part: Option<&std::ffi::OsStr>| part.map(|part| part.to_string_lossy().into_owned());
+    path.set(
+        "name",
+        lua.create_function(move |_, path: String| Ok(lossy(Path::new(&path).file_name())))?,
+    )?;
+    path.set(
+        "stem",
+        lua.create_function(move |_, path: String| Ok(lossy(Path::new(&path).file_stem())))?,
+    )?;
+    path.set(
+        "ext",
+        lua.create_function(move |_, path: String| Ok(lossy(Path::new(&path).extension())))?,
+    )?;
+    path.set(
+        "parent",
+        lua.create_function(|_, path: String| {
+            Ok(Path::new(&path)
+                .parent()
+                .map(|parent| parent.to_string_lossy().into_owned()))
+        })?,
+    )?;
+    path.set(
+        "join",
+        lua.create_function(|_, parts: mlua::Variadic<String>| {
+            Ok(parts
+                .iter()
+                .collect::<PathBuf>()
+                .to_string_lossy()
+                .into_owned())
+        })?,
+    )?;
+    path.set(
+        "relative",
+        lua.create_function(|_, (path, base): (String, String)| {
+            Ok(relative(Path::new(&path), Path::new(&base))
+                .to_string_lossy()
+                .into_owned())
+        })?,
+    )?;
+    Ok(path)
+}
+
+// Expresses `path` relative to `base`, climbing out of `base` with `..` where needed
+fn relative(path: &Path, base: &Path) -> PathBuf {
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    let common = path
+        .iter()
+        .zip(&base)
+        .take_while(|(part, base_part)| part == base_part)
+        .count();
+    let mut relative: PathBuf = base[common..]
+        .iter()
+        .filter(|part| !matches!(part, Component::CurDir))
+        .map(|_| Component::ParentDir)
+        .collect();
+    relative.extend(&path[common..]);
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+fn profile_table(lua: &Lua, profile: &Profile) -> mlua::Result<Table> {
+    let table = lua.create_table()?;
+    table.set("id", profile.id)?;
+    table.set("engine", profile.engine.as_str())?;
+    table.set("source_root", profile.source_root.as_str())?;
+    table.set(
+        "source_file_extensions",
+        profile.source_file_extensions.clone(),
+    )?;
+    table.set("output_root", profile.output_root.as_str())?;
+    table.set(
+        "output_file_extension",
+        profile.output_file_extension.as_str(),
+    )?;
+    table.set("tags", profile.tags.clone().unwrap_or_default())?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_quote_wraps_plain_words_and_spaces() {
+        assert_eq!(shell_quote("notes.md"), "'notes.md'");
+        assert_eq!(shell_quote("my notes.md"), "'my notes.md'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn shell_quote_closes_the_quote_around_embedded_quotes() {
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote("''"), r"''\'''\'''");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shell_quote_survives_the_shell() {
+        for value in ["it's a 'test'", "two\nlines", "$HOME `id` \\ \"*\""] {
+            let output = Command::new("/bin/sh")
+                .arg("-c")
+                .arg(format!("printf %s {}", shell_quote(value)))
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn relative_descends_into_base() {
+        let relative = |path, base| relative(Path::new(path), Path::new(base));
+        assert_eq!(relative("/notes/a/b.md", "/notes"), Path::new("a/b.md"));
+        assert_eq!(relative("/notes", "/notes"), Path::new("."));
+        assert_eq!(relative("/notes/./a.md", "/notes"), Path::new("a.md"));
+    }
+
+    #[test]
+    fn relative_climbs_out_of_base() {
+        let relative = |path, base| relative(Path::new(path), Path::new(base));
+        assert_eq!(
+            relative("/notes/a.md", "/notes/b/c"),
+            Path::new("../../a.md")
+        );
+        assert_eq!(relative("/img/a.png", "/notes"), Path::new("../img/a.png"));
+        assert_eq!(relative("/notes", "/notes/b"), Path::new(".."));
+    }
+}
@@ -1,14 +1,682 @@
-use crate::controller::{AgentEvent, InterfaceArgs};
+use crate::controller::{
+    self, AgentEvent, AgentMessageBroker, Controller, ConverterArgs, InterfaceArgs, Profile,
+    Response,
+};
+use crate::converter::ConverterFactory;
+use crate::engine;
+use crate::error::TernError;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::Line,
+    widgets::{Block, Gauge, List, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+use serde_json::Value;
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
+};
 use tokio::sync::mpsc::Sender;
 
 pub trait Interface: Send {
     fn spawn_and_run(&mut self);
 }
 
-pub struct CommandLineInterface;
+/// Terminal profile manager, for machines where the GUI cannot run (e.g. over SSH)
+pub struct CommandLineInterface {
+    pub tx: Option<Sender<AgentEvent>>,
+    pub args: Option<InterfaceArgs>,
+}
 
 impl Interface for CommandLineInterface {
     fn spawn_and_run(&mut self) {
-        println!("Tern Core: Batch conversion complete (TUI not yet implemented).");
+        let args = self.args.take().unwrap();
+        let mut app = TerminalApp::new(self.tx.clone().unwrap(), args.converter_args);
+        app.load_profiles();
+        let mut terminal = ratatui::init();
+        let result = app.run(&mut terminal);
+        ratatui::restore();
+        if let Err(err) = result {
+            eprintln!("\x1b[1;31m{}\x1b[0m", TernError::from(err));
+        }
+        self.clean();
+    }
+}
+
+impl CommandLineInterface {
+    fn clean(&mut self) {
+        let _runtime_guard = controller::get_runtime_handle().enter();
+        let message_handle = async {
+            Controller::send_quit_event(self.tx.clone().unwrap()).await;
+        };
+        futures::executor::block_on(message_handle);
+        drop(self.tx.take());
+    }
+}
+
+// form fields, the conversion engine is chosen among the available engines
+const FIELDS: [&str; 13] = [
+    "Conversion engine",
+    "Source root",
+    "Source file extensions",
+    "Case-insensitive extensions",
+    "Output root",
+    "Output file extension",
+    "Output template",
+    "Options",
+    "Further targets",
+    "Ignore patterns",
+    "Tags",
+    "Max parallel conversions",
+    "Timeout (seconds)",
+];
+const ENGINE: usize = 0;
+const SOURCE_ROOT: usize = 1;
+const SOURCE_FILE_EXTENSIONS: usize = 2;
+const CASE_INSENSITIVE: usize = 3;
+const OUTPUT_ROOT: usize = 4;
+const OUTPUT_FILE_EXTENSION: usize = 5;
+const OUTPUT_TEMPLATE: usize = 6;
+const OPTIONS: usize = 7;
+const TARGETS: usize = 8;
+const IGNORE_PATTERNS: usize = 9;
+const TAGS: usize = 10;
+const MAX_PARALLEL: usize = 11;
+const TIMEOUT: usize = 12;
+
+enum Mode {
+    Browse,
+    Edit,
+    ConfirmDelete,
+    Run,
+}
+
+#[derive(Default)]
+struct Form {
+    // None while a new profile is being added
+    id: Option<u8>,
+    focus: usize,
+    values: [String; 13],
+}
+
+impl Form {
+    fn from_profile(profile: &Profile) -> Self {
+        let flatten_vector = |v: &Option<Vec<String>>| v.as_deref().unwrap_or_default().join("\n");
+        Self {
+            id: Some(profile.id),
+            focus: 0,
+            values: [
+                profile.engine.clone(),
+                profile.source_root.clone(),
+                profile.source_file_extensions.join(", "),
+                Self::yes_no(profile.case_insensitive),
+                profile.output_root.clone(),
+                profile.output_file_extension.clone(),
+                profile.output_template.clone().unwrap_or_default(),
+                flatten_vector(&profile.options),
+                // one target per line, its extension followed by its options
+                profile
+                    .targets
+                    .iter()
+                    .flatten()
+                    .map(controller::format_target)
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                flatten_vector(&profile.ignore_patterns),
+                flatten_vector(&profile.tags),
+                profile
+                    .max_parallel
+                    .map(|max_parallel| max_parallel.to_string())
+                    .unwrap_or_default(),
+                profile
+                    .timeout
+                    .map(|timeout| timeout.to_string())
+                    .unwrap_or_default(),
+            ],
+        }
+    }
+
+    fn to_profile(&self) -> Result<Profile, TernError> {
+        let construct_vector = |value: &str| {
+            let lines: Vec<String> = value
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(Into::into)
+                .collect();
+            (!lines.is_empty()).then_some(lines)
+        };
+        let targets = construct_vector(&self.values[TARGETS])
+            .map(|lines| {
+                lines
+                    .iter()
+                    .map(|line| controller::parse_target(line))
+                    .collect()
+            })
+            .transpose()?;
+        // an empty template mirrors the source tree
+        let output_template =
+            Some(self.values[OUTPUT_TEMPLATE].clone()).filter(|template| !template.is_empty());
+        if let Some(output_template) = &output_template {
+            controller::check_output_template(output_template)?;
+        }
+        // empty limits set none
+        let max_parallel =
+            controller::parse_limit("max parallel conversions", &self.values[MAX_PARALLEL])?;
+        let profile = Profile {
+            id: self.id.unwrap_or_default(),
+            engine: self.values[ENGINE].clone(),
+            source_root: self.values[SOURCE_ROOT].clone(),
+            source_file_extensions: controller::parse_source_file_extensions(
+                &self.values[SOURCE_FILE_EXTENSIONS],
+            ),
+            case_insensitive: self.values[CASE_INSENSITIVE] == Self::yes_no(true),
+            output_root: self.values[OUTPUT_ROOT].clone(),
+            output_file_extension: self.values[OUTPUT_FILE_EXTENSION].clone(),
+            output_template,
+            options: construct_vector(&self.values[OPTIONS]),
+            targets,
+            ignore_patterns: construct_vector(&self.values[IGNORE_PATTERNS]),
+            tags: construct_vector(&self.values[TAGS]),
+            max_parallel,
+            timeout: controller::parse_limit("timeout", &self.values[TIMEOUT])?,
+            metadata: None,
+        };
+        controller::check_targets(&profile)?;
+        Ok(profile)
+    }
+
+    fn is_multiline(field: usize) -> bool {
+        field == OPTIONS || field == TARGETS || field == IGNORE_PATTERNS || field == TAGS
+    }
+
+    // choices are cycled through rather than typed
+    fn is_choice(field: usize) -> bool {
+        field == ENGINE || field == CASE_INSENSITIVE
+    }
+
+    fn yes_no(value: bool) -> String {
+        if value { "yes" } else { "no" }.to_string()
+    }
+}
+
+/// Progress of a run started from the TUI, fed by the converter's JSON events
+struct RunView {
+    events: mpsc::Receiver<Value>,
+    interrupt: Arc<AtomicBool>,
+    log: Vec<Line<'static>>,
+    in_progress: Vec<String>,
+    planned: usize,
+    finished: usize,
+    done: bool,
+}
+
+impl RunView {
+    /// Consumes pending events, returns true once the run has just ended
+    fn drain(&mut self) -> bool {
+        loop {
+            match self.events.try_recv() {
+                Ok(event) => self.apply(event),
+                Err(mpsc::TryRecvError::Empty) => return false,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    let ended = !self.done;
+                    self.done = true;
+                    return ended;
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, event: Value) {
+        let text = |key: &str| event[key].as_str().unwrap_or_default().to_string();
+        match event["event"].as_str().unwrap_or_default() {
+            "profile_started" => self
+                .log
+                .push(Line::from(format!("Running '{}' engine", text("engine"))).bold()),
+            "file_planned" => self.planned += 1,
+            "file_started" => self.in_progress.push(text("output_file")),
+            "file_finished" => {
+                let output_file = text("output_file");
+                self.in_progress.retain(|file| *file != output_file);
+                self.finished += 1;
+                let status = text("status");
+                let color = match status.as_str() {
+                    "converted" => Color::Green,
+                    "failed" | "timed_out" => Color::Red,
+                    _ => Color::Yellow,
+                };
+                let mut output = event["output"].as_str().unwrap_or_default().trim().lines();
+                let message = match status.as_str() {
+                    "failed" => output.next_back(),
+                    // the reason leads the output of timed out files
+                    "timed_out" => output.next(),
+                    _ => None,
+                }
+                .map(|line| format!(": {}", line))
+                .unwrap_or_default();
+                self.log.push(
+                    Line::from(format!(
+                        "{} [{}] in {} ms{}",
+                        status,
+                        output_file,
+                        event["duration_ms"].as_u64().unwrap_or_default(),
+                        message
+                    ))
+                    .fg(color),
+                );
+            }
+            "file_logged" => self
+                .log
+                .push(Line::from(format!("[{}] {}", text("source"), text("message"))).dim()),
+            "profile_failed" => self.log.push(
+                Line::from(format!(
+                    "Could not run '{}' engine: {}",
+                    text("engine"),
+                    text("message")
+                ))
+                .fg(Color::Red),
+            ),
+            "run_summary" => self.log.push(
+                Line::from(format!(
+                    "Converted: {}, Skipped: {}, Failed: {}, Interrupted: {}, Timed out: {}, Failed profiles: {}",
+                    event["converted"],
+                    event["skipped"],
+                    event["failed"],
+                    event["interrupted"],
+                    event["timed_out"],
+                    event["failed_profiles"]
+                ))
+                .bold(),
+            ),
+            "error" => self.log.push(Line::from(text("message")).fg(Color::Red)),
+            _ => (),
+        }
+    }
+}
+
+struct TerminalApp {
+    tx: Sender<AgentEvent>,
+    converter_args: ConverterArgs,
+    profiles: Vec<Profile>,
+    engines: Vec<String>,
+    list_state: ListState,
+    mode: Mode,
+    form: Form,
+    run: Option<RunView>,
+    status: Line<'static>,
+    quit: bool,
+}
+
+impl TerminalApp {
+    fn new(tx: Sender<AgentEvent>, converter_args: ConverterArgs) -> Self {
+        Self {
+            tx,
+            converter_args,
+            profiles: Vec::new(),
+            engines: engine::available_engines(),
+            list_state: ListState::default(),
+            mode: Mode::Browse,
+            form: Form::default(),
+            run: None,
+            status: Line::default(),
+            quit: false,
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        while !self.quit {
+            if self.run.as_mut().is_some_and(|run| run.drain()) {
+                // converters may have written to the terminal behind our back
+                terminal.clear()?;
+                self.load_profiles();
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+            if event::poll(Duration::from_millis(100))? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle_key(key);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // requests
+
+    fn request<T>(
+        &self,
+        send: impl AsyncFnOnce(Sender<AgentEvent>, Response<T>),
+    ) -> Result<T, TernError> {
+        let response: Response<T> = Default::default();
+        let _runtime_guard = controller::get_runtime_handle().enter();
+        futures::executor::block_on(send(self.tx.clone(), Arc::clone(&response)));
+        controller::wait_response(&response)
+    }
+
+    fn load_profiles(&mut self) {
+        match self
+            .request(async |tx, response| Controller::send_get_profiles_event(tx, response).await)
+        {
+            Ok(profiles) => self.profiles = profiles,
+            Err(err) => self.set_error(err),
+        }
+        let selected = self
+            .list_state
+            .selected()
+            .map(|index| index.min(self.profiles.len().saturating_sub(1)));
+        self.list_state
+            .select(selected.or((!self.profiles.is_empty()).then_some(0)));
+    }
+
+    fn store_profile(&mut self) {
+        let profile = match self.form.to_profile() {
+            Ok(profile) => Arc::new(profile),
+            Err(err) => return self.set_error(err),
+        };
+        let result = match self.form.id {
+            Some(_) => self.request(async |tx, response| {
+                Controller::send_update_profile_event(tx, profile, response).await
+            }),
+            None => self.request(async |tx, response| {
+                Controller::send_store_profile_event(tx, profile, response).await
+            }),
+        };
+        match result {
+            Ok(_) => {
+                self.status = Line::from("Profile stored");
+                self.mode = Mode::Browse;
+                self.load_profiles();
+            }
+            Err(err) => self.set_error(err),
+        }
+    }
+
+    fn delete_profile(&mut self) {
+        let Some(profile) = self.selected_profile() else {
+            return;
+        };
+        let id = profile.id;
+        match self.request(async |tx, response| {
+            Controller::send_delete_profile_event(tx, id, response).await
+        }) {
+            Ok(_) => {
+                self.status = Line::from(format!("Profile #{} removed", id));
+                self.load_profiles();
+            }
+            Err(err) => self.set_error(err),
+        }
+    }
+
+    fn start_run(&mut self) {
+        let (observer, events) = mpsc::channel();
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let converter = ConverterFactory::build_observed(
+            self.tx.clone(),
+            self.converter_args.clone(),
+            Arc::clone(&interrupt),
+            observer,
+        );
+        let runtime_handle = controller::get_runtime_handle();
+        runtime_handle.clone().spawn_blocking(move || {
+            runtime_handle.block_on(converter.convert());
+        });
+        self.run = Some(RunView {
+            events,
+            interrupt,
+            log: Vec::new(),
+            in_progress: Vec::new(),
+            planned: 0,
+            finished: 0,
+            done: false,
+        });
+        self.status = Line::default();
+        self.mode = Mode::Run;
+    }
+
+    fn selected_profile(&self) -> Option<&Profile> {
+        self.list_state
+            .selected()
+            .and_then(|index| self.profiles.get(index))
+    }
+
+    fn set_error(&mut self, err: TernError) {
+        self.status = Line::from(err.to_string()).fg(Color::Red);
+    }
+
+    // input
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match self.mode {
+            Mode::Browse => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+                KeyCode::Char('c') if control => self.quit = true,
+                KeyCode::Char('j') | KeyCode::Down => self.list_state.select_next(),
+                KeyCode::Char('k') | KeyCode::Up => self.list_state.select_previous(),
+                KeyCode::Char('a') => {
+                    self.form = Form::default();
+                    self.form.values[ENGINE] = self.engines.first().cloned().unwrap_or_default();
+                    self.form.values[CASE_INSENSITIVE] = Form::yes_no(false);
+                    self.mode = Mode::Edit;
+                }
+                KeyCode::Char('e') | KeyCode::Enter => {
+                    if let Some(profile) = self.selected_profile() {
+                        self.form = Form::from_profile(profile);
+                        self.mode = Mode::Edit;
+                    }
+                }
+                KeyCode::Char('d') if self.selected_profile().is_some() => {
+                    self.mode = Mode::ConfirmDelete
+                }
+                KeyCode::Char('r') => self.start_run(),
+                _ => (),
+            },
+            Mode::ConfirmDelete => {
+                if key.code == KeyCode::Char('y') {
+                    self.delete_profile();
+                }
+                self.mode = Mode::Browse;
+            }
+            Mode::Edit => match key.code {
+                KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Char('s') if control => self.store_profile(),
+                KeyCode::Tab | KeyCode::Down => self.cycle_focus(1),
+                KeyCode::Char('j') if control => self.cycle_focus(1),
+                KeyCode::BackTab | KeyCode::Up => self.cycle_focus(FIELDS.len() - 1),
+                KeyCode::Char('k') if control => self.cycle_focus(FIELDS.len() - 1),
+                KeyCode::Left if self.form.focus == ENGINE => {
+                    self.cycle_engine(self.engines.len().saturating_sub(1))
+                }
+                KeyCode::Right if self.form.focus == ENGINE => self.cycle_engine(1),
+                KeyCode::Enter if Form::is_multiline(self.form.focus) => {
+                    self.form.values[self.form.focus].push('\n')
+                }
+                KeyCode::Enter => self.cycle_focus(1),
+                KeyCode::Left | KeyCode::Right | KeyCode::Char(' ')
+                    if self.form.focus == CASE_INSENSITIVE =>
+                {
+                    let toggled = self.form.values[CASE_INSENSITIVE] != Form::yes_no(true);
+                    self.form.values[CASE_INSENSITIVE] = Form::yes_no(toggled);
+                }
+                KeyCode::Backspace if !Form::is_choice(self.form.focus) => {
+                    self.form.values[self.form.focus].pop();
+                }
+                KeyCode::Char(c) if !Form::is_choice(self.form.focus) && !control => {
+                    self.form.values[self.form.focus].push(c)
+                }
+                _ => (),
+            },
+            Mode::Run => {
+                let run = self.run.as_ref().unwrap();
+                match key.code {
+                    KeyCode::Char('c') if !run.done => {
+                        run.interrupt.store(true, Ordering::SeqCst);
+                        self.status =
+                            Line::from("Cancelling subsequent conversions...").fg(Color::Yellow);
+                    }
+                    KeyCode::Char('q') | KeyCode::Esc if run.done => {
+                        self.status = Line::default();
+                        self.mode = Mode::Browse;
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    fn cycle_focus(&mut self, step: usize) {
+        self.form.focus = (self.form.focus + step) % FIELDS.len();
+    }
+
+    fn cycle_engine(&mut self, step: usize) {
+        if self.engines.is_empty() {
+            return;
+        }
+        let current = self
+            .engines
+            .iter()
+            .position(|engine| *engine == self.form.values[ENGINE])
+            .unwrap_or(0);
+        self.form.values[ENGINE] = self.engines[(current + step) % self.engines.len()].clone();
+    }
+
+    // rendering
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status, help] = Layout::vertical([
+            Constraint::Min(0),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [profiles, panel] =
+            Layout::horizontal([Constraint::Length(30), Constraint::Min(0)]).areas(main);
+        let highlight = Style::new().add_modifier(Modifier::REVERSED);
+        let list = List::new(
+            self.profiles
+                .iter()
+                .map(|profile| format!("#{} {}", profile.id, profile.engine)),
+        )
+        .block(Block::bordered().title("Profiles"))
+        .highlight_style(highlight);
+        frame.render_stateful_widget(list, profiles, &mut self.list_state);
+        match self.mode {
+            Mode::Run => self.draw_run(frame, panel),
+            Mode::Edit => self.draw_form(frame, panel, &self.form, true),
+            Mode::Browse | Mode::ConfirmDelete => {
+                if let Some(profile) = self.selected_profile() {
+                    self.draw_form(frame, panel, &Form::from_profile(profile), false);
+                }
+            }
+        }
+        let status_line = match self.mode {
+            Mode::ConfirmDelete => {
+                Line::from("Remove the selected profile and its tracked files? (y/n)")
+                    .fg(Color::Yellow)
+            }
+            _ => self.status.clone(),
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+        let keys = match self.mode {
+            Mode::Browse | Mode::ConfirmDelete => {
+                "j/k select  a add  e edit  d delete  r run  q quit"
+            }
+            Mode::Edit => "tab/shift+tab field  ←/→ choose  ctrl+s store  esc cancel",
+            Mode::Run if self.run.as_ref().is_some_and(|run| run.done) => "esc back",
+            Mode::Run => "c cancel",
+        };
+        frame.render_widget(Paragraph::new(keys).dim(), help);
+    }
+
+    fn draw_form(&self, frame: &mut Frame, area: Rect, form: &Form, editing: bool) {
+        let title = match form.id {
+            Some(id) => format!("Profile #{}", id),
+            None => "New profile".to_string(),
+        };
+        let block = Block::bordered().title(title);
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+        let constraints = (0..FIELDS.len()).map(|field| {
+            if Form::is_multiline(field) {
+                Constraint::Min(4)
+            } else {
+                Constraint::Length(3)
+            }
+        });
+        let areas = Layout::vertical(constraints).split(inner);
+        for (field, (label, area)) in FIELDS.iter().zip(areas.iter()).enumerate() {
+            let focused = editing && form.focus == field;
+            let value = if Form::is_choice(field) && editing {
+                format!("< {} >", form.values[field])
+            } else {
+                form.values[field].clone()
+            };
+            let border_style = if focused {
+                Style::new().fg(Color::Cyan)
+            } else {
+                Style::new()
+            };
+            let block = Block::bordered().title(*label).border_style(border_style);
+            let text_area = block.inner(*area);
+            frame.render_widget(Paragraph::new(value.clone()).block(block), *area);
+            if focused && !Form::is_choice(field) {
+                let last_line = value.split('\n').next_back().unwrap_or_default();
+                let line_count = value.split('\n').count() as u16;
+                frame.set_cursor_position(Position::new(
+                    text_area.x + last_line.chars().count() as u16,
+                    text_area.y + (line_count - 1).min(text_area.height.saturating_sub(1)),
+                ));
+            }
+        }
+    }
+
+    fn draw_run(&self, frame: &mut Frame, area: Rect) {
+        let Some(run) = &self.run else {
+            return;
+        };
+        let [gauge, in_progress, log] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(run.in_progress.len().min(5) as u16 + 2),
+            Constraint::Min(0),
+        ])
+        .areas(area);
+        let ratio = if run.done {
+            1.0
+        } else {
+            run.finished as f64 / run.planned.max(1) as f64
+        };
+        frame.render_widget(
+            Gauge::default()
+                .block(Block::bordered().title(if run.done { "Run finished" } else { "Running" }))
+                .gauge_style(Style::new().fg(Color::Cyan))
+                .label(format!("{}/{}", run.finished, run.planned))
+                .ratio(ratio.min(1.0)),
+            gauge,
+        );
+        frame.render_widget(
+            Paragraph::new(
+                run.in_progress
+                    .iter()
+                    .map(|file| Line::from(format!("Processing: {}", file)))
+                    .collect::<Vec<_>>(),
+            )
+            .block(Block::bordered().title("In progress")),
+            in_progress,
+        );
+        // keep the latest lines in view
+        let visible = log.height.saturating_sub(2) as usize;
+        let scroll = run.log.len().saturating_sub(visible) as u16;
+        frame.render_widget(
+            Paragraph::new(run.log.clone())
+                .block(Block::bordered().title("Log"))
+                .scroll((scroll, 0)),
+            log,
+        );
     }
 }
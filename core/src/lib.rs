@@ -3,15 +3,22 @@
 pub mod controller;
 pub mod converter;
 pub mod database;
+pub mod engine;
+pub mod error;
+pub mod host;
 pub mod interface;
+pub mod manager;
 
 use clap::Parser;
 use controller::{
-    AgentEvent, ArgParser, ConverterArgs, DatabaseArgs, InterfaceArgs, ReadEvent, WriteEvent,
+    AgentEvent, ArgParser, Command, ConverterArgs, DatabaseArgs, InterfaceArgs, ReadEvent,
+    WriteEvent,
 };
 use converter::ConverterFactory;
 use database::Database;
+use error::TernError;
 use interface::Interface;
+use manager::ProfileManager;
 use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot, Mutex};
 
@@ -27,31 +34,74 @@ where
     let (oneshot_tx, oneshot_rx) = oneshot::channel();
     // database management
     let db_args = DatabaseArgs {
-        profile_manager: args.profile_manager,
+        // the TUI is a profile manager as well
+        profile_manager: args.profile_manager || args.tui,
+        headless: args.command.is_some(),
+        content_hash: args.content_hash,
     };
     let db_arc_mutex = Arc::new(Mutex::new(Database::new(oneshot_tx, db_args)));
     let db = Arc::clone(&db_arc_mutex);
-    db.lock().await.connect().await;
-    db.lock().await.migrate().await;
+    let setup: Result<(), TernError> = try {
+        db.lock().await.connect().await?;
+        db.lock().await.migrate().await?;
+    };
+    if let Err(err) = setup {
+        eprintln!("\x1b[1;31m{}\x1b[0m", err);
+        return;
+    }
     // mpsc channel setup
     let (mpsc_tx, mut mpsc_rx) = mpsc::channel(1);
+    let converter_args = ConverterArgs {
+        hidden: args.ignore_hidden_files,
+        force: args.force,
+        follow_symlinks: args.follow_symlinks,
+        concurrent_profiles: args.concurrent_profiles,
+        jobs: args.jobs,
+        content_hash: args.content_hash,
+        prune: args.prune,
+        dry_run: args.dry_run,
+        retry_failed: args.retry_failed,
+        watch: args.watch,
+        output_format: args.output_format,
+        selection: match &args.command {
+            Some(Command::Run(selection)) => selection.clone(),
+            _ => Default::default(),
+        },
+    };
     // database status receiver
     let agent_handle = async {
         if let Ok(database_event) = oneshot_rx.await {
             match database_event {
                 controller::ModelEvent::ReadEvent => {
-                    let converter_args = ConverterArgs {
-                        hidden: args.ignore_hidden_files,
-                        force: args.force,
-                        follow_symlinks: args.follow_symlinks,
-                        concurrent_profiles: args.concurrent_profiles,
-                    };
-                    controller::get_runtime_handle().spawn(async {
-                        ConverterFactory::build(mpsc_tx, converter_args).run().await
+                    let command = args.command.clone();
+                    let output_format = args.output_format;
+                    let dry_run = args.dry_run;
+                    // agents wait on database responses, which blocks the thread they run on
+                    let runtime_handle = controller::get_runtime_handle();
+                    runtime_handle.clone().spawn_blocking(move || {
+                        runtime_handle.block_on(async move {
+                            let build_converter =
+                                || ConverterFactory::build(mpsc_tx.clone(), converter_args);
+                            match command {
+                                Some(Command::Prune) => build_converter().prune(dry_run).await,
+                                Some(Command::History { limit }) => {
+                                    build_converter().history(limit).await
+                                }
+                                Some(Command::Profile { action }) => {
+                                    ProfileManager::build(mpsc_tx.clone(), output_format)
+                                        .run(action)
+                                        .await
+                                }
+                                Some(Command::Run(_)) | None => build_converter().run().await,
+                            }
+                        })
                     });
                 }
                 controller::ModelEvent::WriteEvent => {
-                    let interface_args = InterfaceArgs { tui: args.tui };
+                    let interface_args = InterfaceArgs {
+                        tui: args.tui,
+                        converter_args,
+                    };
                     let mpsc_tx = mpsc_tx.clone();
                     let interface = interface_factory(mpsc_tx, interface_args);
                     controller::get_runtime_handle().spawn_blocking(move || {
@@ -69,20 +119,54 @@ where
             match control_event {
                 AgentEvent::ReadEvent(read_event) => match read_event {
                     ReadEvent::GetColumn(arc, col) => {
-                        db.lock().await.get_column(arc, &col).await;
+                        controller::respond(arc, db.lock().await.get_column(&col).await);
                     }
                     ReadEvent::GetProfiles(arc) => {
-                        db.lock().await.get_profiles(arc).await;
+                        controller::respond(arc, db.lock().await.get_profiles().await);
                     }
-                },
-                AgentEvent::WriteEvent(write_event) => match write_event {
-                    WriteEvent::StoreProfile(arc) => {
-                        db.lock().await.store_profile(arc).await;
+                    ReadEvent::GetRuns(arc, limit) => {
+                        controller::respond(arc, db.lock().await.get_runs(limit).await);
                     }
-                    WriteEvent::UpdateMetadata(met) => {
-                        db.lock().await.update_metadata(met.0, met.1).await;
+                    ReadEvent::GetFailedFiles(arc) => {
+                        controller::respond(arc, db.lock().await.get_failed_files().await);
                     }
                 },
+                AgentEvent::WriteEvent(write_event) => {
+                    // bookkeeping writes are not awaited by the agent, their errors are reported here
+                    let result = match write_event {
+                        WriteEvent::StoreProfile(arc, response) => {
+                            controller::respond(response, db.lock().await.store_profile(arc).await);
+                            Ok(())
+                        }
+                        WriteEvent::UpdateProfile(arc, response) => {
+                            controller::respond(
+                                response,
+                                db.lock().await.update_profile(arc).await,
+                            );
+                            Ok(())
+                        }
+                        WriteEvent::DeleteProfile(id, response) => {
+                            controller::respond(response, db.lock().await.delete_profile(id).await);
+                            Ok(())
+                        }
+                        WriteEvent::UpdateMetadata(met) => {
+                            db.lock()
+                                .await
+                                .update_metadata(met.0, met.1, met.2, met.3, met.4, met.5)
+                                .await
+                        }
+                        WriteEvent::RemoveMetadata(met) => {
+                            db.lock().await.remove_metadata(met.0, met.1).await
+                        }
+                        WriteEvent::RefreshMtime(met) => {
+                            db.lock().await.refresh_mtime(met.0, met.1, met.2).await
+                        }
+                        WriteEvent::StoreRun(run) => db.lock().await.store_run(run).await,
+                    };
+                    if let Err(err) = result {
+                        eprintln!("\x1b[1;31m{}\x1b[0m", err);
+                    }
+                }
                 AgentEvent::Quit => break,
             };
         }
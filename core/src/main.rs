@@ -3,5 +3,11 @@ use tern_core::run_app;
 
 #[tokio::main]
 async fn main() {
-    run_app(|_, _| Box::new(CommandLineInterface)).await;
+    run_app(|tx, args| {
+        Box::new(CommandLineInterface {
+            tx: Some(tx),
+            args: Some(args),
+        })
+    })
+    .await;
 }
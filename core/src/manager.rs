@@ -0,0 +1,382 @@
+use std::sync::Arc;
+
+use serde_json::json;
+use tokio::sync::mpsc::Sender;
+
+use crate::controller::{
+    self, AgentEvent, AgentMessageBroker, Controller, OutputFormat, Profile, ProfileCommand,
+    Response, Target,
+};
+use crate::engine;
+use crate::error::TernError;
+
+/// Headless counterpart of the profile manager, driven by `tern profile`
+pub struct ProfileManager {
+    tx: Option<Sender<AgentEvent>>,
+    output_format: OutputFormat,
+}
+
+impl ProfileManager {
+    pub fn build(tx: Sender<AgentEvent>, output_format: OutputFormat) -> Self {
+        Self {
+            tx: Some(tx),
+            output_format,
+        }
+    }
+
+    pub async fn run(&self, command: ProfileCommand) {
+        let result: Result<(), TernError> = try {
+            match command {
+                ProfileCommand::Add {
+                    engine,
+                    source_root,
+                    source_file_extensions,
+                    case_insensitive,
+                    output_root,
+                    output_file_extension,
+                    output_template,
+                    flatten,
+                    options,
+                    targets,
+                    ignore_patterns,
+                    tags,
+                    max_parallel,
+                    timeout,
+                } => {
+                    Self::check_engine(&engine)?;
+                    let profile = Profile {
+                        id: 0,
+                        engine,
+                        source_root,
+                        source_file_extensions,
+                        case_insensitive,
+                        output_root,
+                        output_file_extension,
+                        output_template: match flatten {
+                            true => Some(controller::FLATTEN_TEMPLATE.to_string()),
+                            false => output_template.filter(|template| !template.is_empty()),
+                        },
+                        options: Self::non_empty(options),
+                        targets: Self::parse_targets(targets)?,
+                        ignore_patterns: Self::non_empty(ignore_patterns),
+                        tags: Self::non_empty(tags),
+                        // 0 sets no limit, like an empty text field
+                        max_parallel: max_parallel.filter(|max_parallel| *max_parallel > 0),
+                        timeout: timeout.filter(|timeout| *timeout > 0),
+                        metadata: None,
+                    };
+                    Self::check_profile(&profile)?;
+                    let response: Response<()> = Default::default();
+                    let tx = self.tx.clone().unwrap();
+                    Controller::send_store_profile_event(
+                        tx,
+                        Arc::new(profile),
+                        Arc::clone(&response),
+                    )
+                    .await;
+                    controller::wait_response(&response)?;
+                    self.notify("profile_added", None);
+                }
+                ProfileCommand::List => {
+                    let profiles = self.get_profiles().await?;
+                    match self.output_format {
+                        OutputFormat::Text => Self::print_table(&profiles),
+                        OutputFormat::Json => profiles
+                            .iter()
+                            .for_each(|profile| println!("{}", Self::serialize_profile(profile))),
+                    }
+                }
+                ProfileCommand::Show { id } => {
+                    let profile = self.get_profile(id).await?;
+                    match self.output_format {
+                        OutputFormat::Text => Self::print_profile(&profile),
+                        OutputFormat::Json => println!("{}", Self::serialize_profile(&profile)),
+                    }
+                }
+                ProfileCommand::Edit {
+                    id,
+                    engine,
+                    source_root,
+                    source_file_extensions,
+                    case_insensitive,
+                    output_root,
+                    output_file_extension,
+                    output_template,
+                    flatten,
+                    options,
+                    targets,
+                    ignore_patterns,
+                    tags,
+                    max_parallel,
+                    timeout,
+                } => {
+                    let stored = self.get_profile(id).await?;
+                    if let Some(engine) = &engine {
+                        Self::check_engine(engine)?;
+                    }
+                    let profile = Profile {
+                        id,
+                        engine: engine.unwrap_or(stored.engine),
+                        source_root: source_root.unwrap_or(stored.source_root),
+                        source_file_extensions: source_file_extensions
+                            .unwrap_or(stored.source_file_extensions),
+                        case_insensitive: case_insensitive.unwrap_or(stored.case_insensitive),
+                        output_root: output_root.unwrap_or(stored.output_root),
+                        output_file_extension: output_file_extension
+                            .unwrap_or(stored.output_file_extension),
+                        output_template: match (flatten, output_template) {
+                            (true, _) => Some(controller::FLATTEN_TEMPLATE.to_string()),
+                            (false, Some(template)) => (!template.is_empty()).then_some(template),
+                            (false, None) => stored.output_template,
+                        },
+                        options: options.map_or(stored.options, Self::non_empty),
+                        targets: match targets {
+                            Some(targets) => Self::parse_targets(targets)?,
+                            None => stored.targets,
+                        },
+                        ignore_patterns: ignore_patterns
+                            .map_or(stored.ignore_patterns, Self::non_empty),
+                        tags: tags.map_or(stored.tags, Self::non_empty),
+                        max_parallel: match max_parallel {
+                            Some(max_parallel) => (max_parallel > 0).then_some(max_parallel),
+                            None => stored.max_parallel,
+                        },
+                        timeout: match timeout {
+                            Some(timeout) => (timeout > 0).then_some(timeout),
+                            None => stored.timeout,
+                        },
+                        metadata: None,
+                    };
+                    Self::check_profile(&profile)?;
+                    let response: Response<()> = Default::default();
+                    let tx = self.tx.clone().unwrap();
+                    Controller::send_update_profile_event(
+                        tx,
+                        Arc::new(profile),
+                        Arc::clone(&response),
+                    )
+                    .await;
+                    controller::wait_response(&response)?;
+                    self.notify("profile_updated", Some(id));
+                }
+                ProfileCommand::Remove { id } => {
+                    self.get_profile(id).await?;
+                    let response: Response<()> = Default::default();
+                    let tx = self.tx.clone().unwrap();
+                    Controller::send_delete_profile_event(tx, id, Arc::clone(&response)).await;
+                    controller::wait_response(&response)?;
+                    self.notify("profile_removed", Some(id));
+                }
+            }
+        };
+        if let Err(err) = result {
+            match self.output_format {
+                OutputFormat::Text => eprintln!("\x1b[1;31m{}\x1b[0m", err),
+                OutputFormat::Json => println!(
+                    "{}",
+                    json!({ "event": "error", "message": err.to_string() })
+                ),
+            }
+        }
+
+        let tx = self.tx.clone().unwrap();
+        Controller::send_quit_event(tx).await;
+    }
+
+    async fn get_profiles(&self) -> Result<Vec<Profile>, TernError> {
+        let profiles_arc: Response<Vec<Profile>> = Default::default();
+        let tx = self.tx.clone().unwrap();
+        Controller::send_get_profiles_event(tx, Arc::clone(&profiles_arc)).await;
+        controller::wait_response(&profiles_arc)
+    }
+
+    async fn get_profile(&self, id: u8) -> Result<Profile, TernError> {
+        self.get_profiles()
+            .await?
+            .into_iter()
+            .find(|profile| profile.id == id)
+            .ok_or(TernError::ProfileNotFound(id))
+    }
+
+    fn check_profile(profile: &Profile) -> Result<(), TernError> {
+        controller::source_file_matcher(profile)?;
+        controller::check_targets(profile)?;
+        if let Some(output_template) = &profile.output_template {
+            controller::check_output_template(output_template)?;
+        }
+        Ok(())
+    }
+
+    fn check_engine(engine: &str) -> Result<(), TernError> {
+        if engine::engine_exists(engine) {
+            Ok(())
+        } else {
+            Err(TernError::EngineNotFound(engine.to_string()))
+        }
+    }
+
+    // empty lists are stored as NULL, like empty text fields in the profile manager
+    fn non_empty(values: Vec<String>) -> Option<Vec<String>> {
+        let values: Vec<String> = values.into_iter().filter(|it| !it.is_empty()).collect();
+        (!values.is_empty()).then_some(values)
+    }
+
+    fn parse_targets(values: Vec<String>) -> Result<Option<Vec<Target>>, TernError> {
+        Self::non_empty(values)
+            .map(|values| {
+                values
+                    .iter()
+                    .map(|value| controller::parse_target(value))
+                    .collect()
+            })
+            .transpose()
+    }
+
+    fn notify(&self, event: &str, id: Option<u8>) {
+        match self.output_format {
+            OutputFormat::Text => match id {
+                Some(id) => println!("\x1b[1m{}\x1b[0m #{}", event.replace('_', " "), id),
+                None => println!("\x1b[1m{}\x1b[0m", event.replace('_', " ")),
+            },
+            OutputFormat::Json => println!("{}", json!({ "event": event, "profile": id })),
+        }
+    }
+
+    fn print_table(profiles: &[Profile]) {
+        let rows: Vec<[String; 5]> = profiles
+            .iter()
+            .map(|profile| {
+                [
+                    profile.id.to_string(),
+                    profile.engine.clone(),
+                    format!(
+                        "{} ({})",
+                        profile.source_root,
+                        profile.source_file_extensions.join(", ")
+                    ),
+                    format!(
+                        "{} ({})",
+                        profile.output_root,
+                        controller::profile_targets(profile)
+                            .iter()
+                            .map(|target| target.output_file_extension.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    profile.tags.as_deref().unwrap_or_default().join(", "),
+                ]
+            })
+            .collect();
+        let header = ["ID", "ENGINE", "SOURCE", "OUTPUT", "TAGS"].map(String::from);
+        let widths: Vec<usize> = (0..header.len())
+            .map(|column| {
+                rows.iter()
+                    .chain([&header])
+                    .map(|row| row[column].chars().count())
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let format_row = |row: &[String; 5]| {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        };
+        println!("\x1b[1m{}\x1b[0m", format_row(&header));
+        rows.iter().for_each(|row| println!("{}", format_row(row)));
+    }
+
+    fn print_profile(profile: &Profile) {
+        let list = |values: &Option<Vec<String>>| {
+            values
+                .as_deref()
+                .unwrap_or_default()
+                .iter()
+                .map(|value| format!("\n  {}", value))
+                .collect::<String>()
+        };
+        println!("\x1b[1mProfile #{}\x1b[0m", profile.id);
+        println!("engine: {}", profile.engine);
+        println!("source root: {}", profile.source_root);
+        println!(
+            "source file extensions: {}{}",
+            profile.source_file_extensions.join(", "),
+            if profile.case_insensitive {
+                " (case-insensitive)"
+            } else {
+                ""
+            }
+        );
+        println!("output root: {}", profile.output_root);
+        println!("output file extension: {}", profile.output_file_extension);
+        println!(
+            "output template: {}",
+            profile
+                .output_template
+                .as_deref()
+                .unwrap_or("(mirrors the source tree)")
+        );
+        println!("options:{}", list(&profile.options));
+        println!(
+            "targets:{}",
+            list(
+                &profile
+                    .targets
+                    .as_ref()
+                    .map(|targets| targets.iter().map(controller::format_target).collect())
+            )
+        );
+        println!("ignore patterns:{}", list(&profile.ignore_patterns));
+        println!("tags:{}", list(&profile.tags));
+        println!(
+            "max parallel: {}",
+            profile
+                .max_parallel
+                .map(|max_parallel| max_parallel.to_string())
+                .as_deref()
+                .unwrap_or("(as many as the run's jobs)")
+        );
+        println!(
+            "timeout: {}",
+            profile
+                .timeout
+                .map(|timeout| format!("{} s", timeout))
+                .as_deref()
+                .unwrap_or("(none)")
+        );
+        println!(
+            "\x1b[2mtracked files: {}\x1b[0m",
+            profile
+                .metadata
+                .as_ref()
+                .map_or(0, |metadata| metadata.len())
+        );
+    }
+
+    fn serialize_profile(profile: &Profile) -> serde_json::Value {
+        json!({
+            "id": profile.id,
+            "engine": profile.engine,
+            "source_root": profile.source_root,
+            "source_file_extensions": profile.source_file_extensions,
+            "case_insensitive": profile.case_insensitive,
+            "output_root": profile.output_root,
+            "output_file_extension": profile.output_file_extension,
+            "output_template": profile.output_template,
+            "options": profile.options.as_deref().unwrap_or_default(),
+            "targets": profile.targets.iter().flatten().map(|target| json!({
+                "output_file_extension": target.output_file_extension,
+                "options": target.options.as_deref().unwrap_or_default(),
+            })).collect::<Vec<_>>(),
+            "ignore_patterns": profile.ignore_patterns.as_deref().unwrap_or_default(),
+            "tags": profile.tags.as_deref().unwrap_or_default(),
+            "max_parallel": profile.max_parallel,
+            "timeout": profile.timeout,
+            "tracked_files": profile.metadata.as_ref().map_or(0, |metadata| metadata.len()),
+        })
+    }
+}
@@ -3,7 +3,8 @@ slint::include_modules!();
 use std::rc::Rc;
 use slint::{Model, SharedString, VecModel};
 
-use tern_core::controller::{self, AgentEvent, AgentMessageBroker, Controller, Profile};
+use tern_core::controller::{self, AgentEvent, AgentMessageBroker, Controller, Profile, Response};
+use tern_core::engine;
 use tern_core::interface::Interface;
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
@@ -34,33 +35,65 @@ impl GraphicalInterface {
             .unwrap()
             .global::<Backend>()
             .on_set_stored_and_available_engines(move || {
-                let stored_engines_arc = Default::default();
+                let stored_profiles_arc: Response<Vec<Profile>> = Default::default();
                 let _runtime_guard = controller::get_runtime_handle().enter();
                 let message_handle = async {
-                    Controller::send_get_column_event(
+                    Controller::send_get_profiles_event(
                         tx.clone().unwrap(),
-                        Arc::clone(&stored_engines_arc),
-                        "engine".to_string(),
+                        Arc::clone(&stored_profiles_arc),
                     )
                     .await;
                 };
                 futures::executor::block_on(message_handle);
-                let (lock, cvar) = &*stored_engines_arc;
-                let stored_engines = lock.lock().unwrap();
-                drop(cvar.wait(stored_engines).unwrap());
-                let stored_engines = Arc::try_unwrap(stored_engines_arc)
-                    .unwrap()
-                    .0
-                    .into_inner()
-                    .unwrap();
-                let stored_engines_model: Rc<VecModel<SharedString>> = Rc::new(VecModel::from(
-                    stored_engines
+                let stored_profiles = match controller::wait_response(&stored_profiles_arc) {
+                    Ok(stored_profiles) => stored_profiles,
+                    Err(err) => {
+                        app.global::<Backend>()
+                            .set_error_message(err.to_string().into());
+                        Vec::new()
+                    }
+                };
+                let flatten_vector = |v: Option<Vec<String>>| -> SharedString {
+                    v.map(|lines| lines.join("\n")).unwrap_or_default().into()
+                };
+                let stored_profiles_model: Rc<VecModel<StoredProfile>> = Rc::new(VecModel::from(
+                    stored_profiles
                         .into_iter()
-                        .map(Into::into)
+                        .map(|profile| StoredProfile {
+                            id: profile.id as i32,
+                            engine: profile.engine.into(),
+                            max_parallel: profile
+                                .max_parallel
+                                .map(|max_parallel| max_parallel.to_string())
+                                .unwrap_or_default()
+                                .into(),
+                            timeout: profile
+                                .timeout
+                                .map(|timeout| timeout.to_string())
+                                .unwrap_or_default()
+                                .into(),
+                            source_root: profile.source_root.into(),
+                            source_file_extensions: profile.source_file_extensions.join(", ").into(),
+                            case_insensitive: profile.case_insensitive,
+                            output_root: profile.output_root.into(),
+                            output_file_extension: profile.output_file_extension.into(),
+                            output_template: profile.output_template.unwrap_or_default().into(),
+                            options: flatten_vector(profile.options),
+                            targets: profile
+                                .targets
+                                .iter()
+                                .flatten()
+                                .map(controller::format_target)
+                                .collect::<Vec<_>>()
+                                .join("\n")
+                                .into(),
+                            ignore_patterns: flatten_vector(profile.ignore_patterns),
+                            tags: flatten_vector(profile.tags),
+                        })
                         .collect::<Vec<_>>(),
                 ));
                 app.global::<Backend>()
-                    .set_stored_engines(stored_engines_model.into());
+                    .set_stored_profiles(stored_profiles_model.into());
             });
         self.app
             .as_ref()
@@ -69,9 +102,7 @@ impl GraphicalInterface {
             .invoke_set_stored_and_available_engines();
         // set available engines
         let app = app_weak.unwrap();
-        let available_engines: Vec<String> = controller::read_data_dir()
-            .filter_map(|entry| entry.ok().and_then(|e| e.file_name().into_string().ok()))
-            .collect();
+        let available_engines = engine::available_engines();
         let available_engines_model: Rc<VecModel<SharedString>> = Rc::new(VecModel::from(
             available_engines
                 .into_iter()
@@ -121,30 +152,118 @@ impl GraphicalInterface {
                     }};
                 }
                 let options = construct_vector_from_getter!(get_options);
+                let targets = match construct_vector_from_getter!(get_targets)
+                    .map(|lines| lines.iter().map(|line| controller::parse_target(line)).collect())
+                    .transpose()
+                {
+                    Ok(targets) => targets,
+                    Err(err) => {
+                        app.global::<Backend>().set_error_message(err.to_string().into());
+                        return;
+                    }
+                };
                 let ignore_patterns = construct_vector_from_getter!(get_ignore_patterns);
+                let tags = construct_vector_from_getter!(get_tags);
+                // an empty template mirrors the source tree
+                let output_template = Some(app.global::<Backend>().get_output_template().to_string())
+                    .filter(|template| !template.is_empty());
+                if let Some(Err(err)) = output_template.as_deref().map(controller::check_output_template) {
+                    app.global::<Backend>().set_error_message(err.to_string().into());
+                    return;
+                }
+                // empty limits set none
+                let limits = controller::parse_limit("max parallel conversions", &app.global::<Backend>().get_max_parallel())
+                    .and_then(|max_parallel| {
+                        Ok((max_parallel, controller::parse_limit("timeout", &app.global::<Backend>().get_timeout())?))
+                    });
+                let (max_parallel, timeout) = match limits {
+                    Ok(limits) => limits,
+                    Err(err) => {
+                        app.global::<Backend>().set_error_message(err.to_string().into());
+                        return;
+                    }
+                };
+                // a selected profile is updated in place, otherwise a new one is stored
+                let selected_profile_id = app.global::<Backend>().get_selected_profile_id();
                 let profile_arc = Arc::new(Profile {
-                    id: 0,
+                    id: selected_profile_id.max(0) as u8,
                     engine: app.global::<Backend>().get_engine().to_string(),
                     source_root: app.global::<Backend>().get_source_root().to_string(),
-                    source_file_extension: app
-                        .global::<Backend>()
-                        .get_source_file_extension()
-                        .to_string(),
+                    source_file_extensions: controller::parse_source_file_extensions(
+                        &app.global::<Backend>().get_source_file_extensions(),
+                    ),
+                    case_insensitive: app.global::<Backend>().get_case_insensitive(),
                     output_root: app.global::<Backend>().get_output_root().to_string(),
                     output_file_extension: app
                         .global::<Backend>()
                         .get_output_file_extension()
                         .to_string(),
+                    output_template,
                     options,
+                    targets,
                     ignore_patterns,
+                    tags,
+                    max_parallel,
+                    timeout,
                     metadata: None,
                 });
+                if let Err(err) = controller::check_targets(&profile_arc) {
+                    app.global::<Backend>().set_error_message(err.to_string().into());
+                    return;
+                }
                 let profile = Arc::clone(&profile_arc);
+                let response: Response<()> = Default::default();
+                let _runtime_guard = controller::get_runtime_handle().enter();
+                let message_handle = async {
+                    if selected_profile_id >= 0 {
+                        Controller::send_update_profile_event(
+                            tx.clone().unwrap(),
+                            profile,
+                            Arc::clone(&response),
+                        )
+                        .await;
+                    } else {
+                        Controller::send_store_profile_event(
+                            tx.clone().unwrap(),
+                            profile,
+                            Arc::clone(&response),
+                        )
+                        .await;
+                    }
+                };
+                futures::executor::block_on(message_handle);
+                let error_message = controller::wait_response(&response)
+                    .err()
+                    .map(|err| err.to_string())
+                    .unwrap_or_default();
+                app.global::<Backend>()
+                    .set_error_message(error_message.into());
+            });
+        // delete profile
+        let app = app_weak.unwrap();
+        let tx = self.tx.clone();
+        self.app
+            .as_ref()
+            .unwrap()
+            .global::<Backend>()
+            .on_delete_profile(move |profile_id: i32| {
+                let response: Response<()> = Default::default();
                 let _runtime_guard = controller::get_runtime_handle().enter();
                 let message_handle = async {
-                    Controller::send_store_profile_event(tx.clone().unwrap(), profile).await;
+                    Controller::send_delete_profile_event(
+                        tx.clone().unwrap(),
+                        profile_id as u8,
+                        Arc::clone(&response),
+                    )
+                    .await;
                 };
                 futures::executor::block_on(message_handle);
+                let error_message = controller::wait_response(&response)
+                    .err()
+                    .map(|err| err.to_string())
+                    .unwrap_or_default();
+                app.global::<Backend>()
+                    .set_error_message(error_message.into());
             });
     }
 
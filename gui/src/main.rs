@@ -8,7 +8,10 @@ use interface::GraphicalInterface;
 
 fn build_interface(tx: Sender<AgentEvent>, args: InterfaceArgs) -> Box<dyn Interface> {
     if args.tui {
-        Box::new(CommandLineInterface)
+        Box::new(CommandLineInterface {
+            tx: Some(tx),
+            args: Some(args),
+        })
     } else {
         Box::new(GraphicalInterface {
             tx: Some(tx),